@@ -0,0 +1,370 @@
+use crate::mesh_geo;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+const EPSILON: f64 = 0.00001;
+
+type Vec2 = (f64, f64);
+
+fn add(a: Vec2, b: Vec2) -> Vec2 {
+    return (a.0 + b.0, a.1 + b.1);
+}
+
+fn sub(a: Vec2, b: Vec2) -> Vec2 {
+    return (a.0 - b.0, a.1 - b.1);
+}
+
+fn mul(a: Vec2, k: f64) -> Vec2 {
+    return (a.0 * k, a.1 * k);
+}
+
+fn dot(a: Vec2, b: Vec2) -> f64 {
+    return a.0 * b.0 + a.1 * b.1;
+}
+
+fn det(a: Vec2, b: Vec2) -> f64 {
+    return a.0 * b.1 - a.1 * b.0;
+}
+
+fn abs_sq(a: Vec2) -> f64 {
+    return dot(a, a);
+}
+
+fn normalize(a: Vec2) -> Vec2 {
+    let len = abs_sq(a).sqrt();
+    if len == 0.0 {
+        return (0.0, 0.0);
+    }
+    return mul(a, 1.0 / len);
+}
+
+// allowed velocities are on the left of the line
+#[derive(Clone, Copy)]
+struct OrcaLine {
+    point: Vec2,
+    direction: Vec2,
+}
+
+pub struct Agent {
+    pub pos: Vec2,
+    pub z: f32,
+    pub radius: f64,
+    pub max_speed: f64,
+    pub velocity: Vec2,
+    pub preferred: Vec2,
+}
+
+struct CrowdState {
+    agents: BTreeMap<u64, Agent>,
+}
+
+// agents steered with ORCA, times in seconds and speeds in units per second
+pub struct Crowd {
+    pub map_id: i64,
+    // agents further apart than this are not considered
+    pub neighbor_dist: f64,
+    // how far ahead collisions with other agents are avoided
+    pub time_horizon: f64,
+    // how far ahead collisions with walls are avoided
+    pub time_horizon_obst: f64,
+    state: Mutex<CrowdState>,
+}
+
+// finite and above zero
+fn positive(v: f64) -> bool {
+    return v.is_finite() && v > 0.0;
+}
+
+// ORCA lines for walls, keeping the agent time_horizon_obst away from hitting them
+fn wall_lines(map: &mesh_geo::Map, agent: &Agent, time_horizon_obst: f64) -> Vec<OrcaLine> {
+    // a fast agent only looks MAX_WALL_RADIUS ahead for walls
+    let range = (agent.radius + agent.max_speed * time_horizon_obst).min(mesh_geo::MAX_WALL_RADIUS);
+    let mut lines = vec![];
+    for (d, point, normal) in mesh_geo::walls_near(map, agent.pos.0, agent.pos.1, range) {
+        // away from the closest point, the wall normal when standing on it
+        let n = if d > EPSILON { mul(sub(agent.pos, point), 1.0 / d) } else { normal };
+        lines.push(OrcaLine {
+            point: mul(n, (agent.radius - d) / time_horizon_obst),
+            direction: (n.1, -n.0),
+        });
+    }
+    return lines;
+}
+
+fn agent_line(agent: &Agent, other: &Agent, time_horizon: f64, dt: f64) -> OrcaLine {
+    let inv_time_horizon = 1.0 / time_horizon;
+    let relative_position = sub(other.pos, agent.pos);
+    let relative_velocity = sub(agent.velocity, other.velocity);
+    let dist_sq = abs_sq(relative_position);
+    let combined_radius = agent.radius + other.radius;
+    let combined_radius_sq = combined_radius * combined_radius;
+
+    let direction;
+    let u;
+    if dist_sq > combined_radius_sq {
+        // no collision, vector from cutoff center to relative velocity
+        let w = sub(relative_velocity, mul(relative_position, inv_time_horizon));
+        let w_length_sq = abs_sq(w);
+        let dot_product1 = dot(w, relative_position);
+
+        if dot_product1 < 0.0 && dot_product1 * dot_product1 > combined_radius_sq * w_length_sq {
+            // project on cut-off circle
+            let w_length = w_length_sq.sqrt();
+            let unit_w = mul(w, 1.0 / w_length);
+            direction = (unit_w.1, -unit_w.0);
+            u = mul(unit_w, combined_radius * inv_time_horizon - w_length);
+        } else {
+            // project on legs
+            let leg = (dist_sq - combined_radius_sq).sqrt();
+            if det(relative_position, w) > 0.0 {
+                direction = mul(
+                    (
+                        relative_position.0 * leg - relative_position.1 * combined_radius,
+                        relative_position.0 * combined_radius + relative_position.1 * leg,
+                    ),
+                    1.0 / dist_sq,
+                );
+            } else {
+                direction = mul(
+                    (
+                        relative_position.0 * leg + relative_position.1 * combined_radius,
+                        -relative_position.0 * combined_radius + relative_position.1 * leg,
+                    ),
+                    -1.0 / dist_sq,
+                );
+            }
+            let dot_product2 = dot(relative_velocity, direction);
+            u = sub(mul(direction, dot_product2), relative_velocity);
+        }
+    } else {
+        // already colliding, resolve within this step
+        let inv_time_step = 1.0 / dt;
+        let w = sub(relative_velocity, mul(relative_position, inv_time_step));
+        let w_length = abs_sq(w).sqrt();
+        let unit_w = if w_length > 0.0 { mul(w, 1.0 / w_length) } else { (1.0, 0.0) };
+        direction = (unit_w.1, -unit_w.0);
+        u = mul(unit_w, combined_radius * inv_time_step - w_length);
+    }
+    // each agent takes half of the avoidance
+    return OrcaLine {
+        point: add(agent.velocity, mul(u, 0.5)),
+        direction: direction,
+    };
+}
+
+fn linear_program1(
+    lines: &Vec<OrcaLine>,
+    line_no: usize,
+    radius: f64,
+    opt_velocity: Vec2,
+    direction_opt: bool,
+    result: &mut Vec2,
+) -> bool {
+    let line = lines[line_no];
+    let dot_product = dot(line.point, line.direction);
+    let discriminant = dot_product * dot_product + radius * radius - abs_sq(line.point);
+    if discriminant < 0.0 {
+        // max speed circle fully invalidates the line
+        return false;
+    }
+    let sqrt_discriminant = discriminant.sqrt();
+    let mut t_left = -dot_product - sqrt_discriminant;
+    let mut t_right = -dot_product + sqrt_discriminant;
+
+    for i in 0..line_no {
+        let denominator = det(line.direction, lines[i].direction);
+        let numerator = det(lines[i].direction, sub(line.point, lines[i].point));
+        if denominator.abs() <= EPSILON {
+            // parallel lines
+            if numerator < 0.0 {
+                return false;
+            }
+            continue;
+        }
+        let t = numerator / denominator;
+        if denominator >= 0.0 {
+            t_right = t_right.min(t);
+        } else {
+            t_left = t_left.max(t);
+        }
+        if t_left > t_right {
+            return false;
+        }
+    }
+
+    if direction_opt {
+        if dot(opt_velocity, line.direction) > 0.0 {
+            *result = add(line.point, mul(line.direction, t_right));
+        } else {
+            *result = add(line.point, mul(line.direction, t_left));
+        }
+    } else {
+        let t = dot(line.direction, sub(opt_velocity, line.point));
+        if t < t_left {
+            *result = add(line.point, mul(line.direction, t_left));
+        } else if t > t_right {
+            *result = add(line.point, mul(line.direction, t_right));
+        } else {
+            *result = add(line.point, mul(line.direction, t));
+        }
+    }
+    return true;
+}
+
+// returns the index of the first line that could not be satisfied, or lines.len()
+fn linear_program2(
+    lines: &Vec<OrcaLine>,
+    radius: f64,
+    opt_velocity: Vec2,
+    direction_opt: bool,
+    result: &mut Vec2,
+) -> usize {
+    if direction_opt {
+        *result = mul(opt_velocity, radius);
+    } else if abs_sq(opt_velocity) > radius * radius {
+        *result = mul(normalize(opt_velocity), radius);
+    } else {
+        *result = opt_velocity;
+    }
+
+    for i in 0..lines.len() {
+        if det(lines[i].direction, sub(lines[i].point, *result)) > 0.0 {
+            let temp_result = *result;
+            if !linear_program1(lines, i, radius, opt_velocity, direction_opt, result) {
+                *result = temp_result;
+                return i;
+            }
+        }
+    }
+    return lines.len();
+}
+
+// infeasible, minimize the largest violation of the agent lines keeping the wall lines
+fn linear_program3(lines: &Vec<OrcaLine>, num_obst_lines: usize, begin_line: usize, radius: f64, result: &mut Vec2) {
+    let mut distance = 0.0;
+    for i in begin_line..lines.len() {
+        if det(lines[i].direction, sub(lines[i].point, *result)) > distance {
+            let mut proj_lines: Vec<OrcaLine> = lines[0..num_obst_lines].to_vec();
+            for j in num_obst_lines..i {
+                let determinant = det(lines[i].direction, lines[j].direction);
+                let point;
+                if determinant.abs() <= EPSILON {
+                    if dot(lines[i].direction, lines[j].direction) > 0.0 {
+                        // same direction
+                        continue;
+                    }
+                    point = mul(add(lines[i].point, lines[j].point), 0.5);
+                } else {
+                    let t = det(lines[j].direction, sub(lines[i].point, lines[j].point)) / determinant;
+                    point = add(lines[i].point, mul(lines[i].direction, t));
+                }
+                proj_lines.push(OrcaLine {
+                    point: point,
+                    direction: normalize(sub(lines[j].direction, lines[i].direction)),
+                });
+            }
+
+            let temp_result = *result;
+            let opt = (-lines[i].direction.1, lines[i].direction.0);
+            if linear_program2(&proj_lines, radius, opt, true, result) < proj_lines.len() {
+                // should not happen, keep the previous result
+                *result = temp_result;
+            }
+            distance = det(lines[i].direction, sub(lines[i].point, *result));
+        }
+    }
+}
+
+impl Crowd {
+    // None unless neighbor_dist and time_horizon are positive, the solver divides by them
+    pub fn new(map_id: i64, neighbor_dist: f64, time_horizon: f64) -> Option<Crowd> {
+        if !positive(neighbor_dist) || !positive(time_horizon) {
+            return None;
+        }
+        return Some(Crowd {
+            map_id: map_id,
+            neighbor_dist: neighbor_dist,
+            time_horizon: time_horizon,
+            time_horizon_obst: time_horizon / 2.0,
+            state: Mutex::new(CrowdState { agents: BTreeMap::new() }),
+        });
+    }
+
+    // false when the position is off the navmesh or radius and max_speed are not positive
+    pub fn add_agent(&self, map: &mesh_geo::Map, id: u64, pos: Vec2, radius: f64, max_speed: f64) -> bool {
+        if !positive(radius) || !positive(max_speed) {
+            return false;
+        }
+        let node = match mesh_geo::find_node_at(map, pos.0, pos.1) {
+            Some(node) => node,
+            None => return false,
+        };
+        let z = mesh_geo::get_z_from_poly(pos.0 as i32, pos.1 as i32, &map.graph[node].1);
+        let agent = Agent {
+            pos: pos,
+            z: z,
+            radius: radius,
+            max_speed: max_speed,
+            velocity: (0.0, 0.0),
+            preferred: (0.0, 0.0),
+        };
+        self.state.lock().unwrap().agents.insert(id, agent);
+        return true;
+    }
+
+    pub fn remove_agent(&self, id: u64) -> bool {
+        return self.state.lock().unwrap().agents.remove(&id).is_some();
+    }
+
+    // desired velocity, usually towards the next point of the agent's path
+    pub fn set_preferred_velocity(&self, id: u64, velocity: Vec2) -> bool {
+        match self.state.lock().unwrap().agents.get_mut(&id) {
+            Some(agent) => {
+                agent.preferred = velocity;
+                return true;
+            }
+            None => return false,
+        }
+    }
+
+    // moves every agent by dt, returns (id, position, velocity) per agent
+    pub fn step(&self, map: &mesh_geo::Map, dt: f64) -> Vec<(u64, (f64, f64, f32), Vec2)> {
+        let mut state = self.state.lock().unwrap();
+
+        let mut velocities = vec![];
+        for (id, agent) in &state.agents {
+            let mut lines = wall_lines(map, agent, self.time_horizon_obst);
+            let num_obst_lines = lines.len();
+            for (other_id, other) in &state.agents {
+                if other_id == id || abs_sq(sub(other.pos, agent.pos)) > self.neighbor_dist * self.neighbor_dist {
+                    continue;
+                }
+                lines.push(agent_line(agent, other, self.time_horizon, dt));
+            }
+
+            let mut velocity = (0.0, 0.0);
+            let line_fail = linear_program2(&lines, agent.max_speed, agent.preferred, false, &mut velocity);
+            if line_fail < lines.len() {
+                linear_program3(&lines, num_obst_lines, line_fail, agent.max_speed, &mut velocity);
+            }
+            velocities.push((*id, velocity));
+        }
+
+        let mut res = vec![];
+        for (id, velocity) in velocities {
+            let agent = state.agents.get_mut(&id).unwrap();
+            let to = add(agent.pos, mul(velocity, dt));
+            // the navmesh has the last word
+            match mesh_geo::move_along_surface(map, agent.pos, to) {
+                Some(((x, y, z), _)) => {
+                    agent.velocity = mul(sub((x, y), agent.pos), 1.0 / dt);
+                    agent.pos = (x, y);
+                    agent.z = z;
+                }
+                None => agent.velocity = (0.0, 0.0),
+            }
+            res.push((id, (agent.pos.0, agent.pos.1, agent.z), agent.velocity));
+        }
+        return res;
+    }
+}
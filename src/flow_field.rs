@@ -0,0 +1,101 @@
+use crate::mesh_geo;
+use crate::mesh_geo::{Line, PrePoly};
+use petgraph::graph::NodeIndex;
+use std::collections::HashMap;
+
+pub struct FlowCell {
+    pub poly: PrePoly,
+    // portal towards the target, None for the target polygon
+    pub portal: Option<Line>,
+}
+
+pub struct FlowField {
+    pub map_id: i64,
+    pub target: (i64, i64),
+    // map revision the field was built on, the node indices only hold for it
+    pub revision: u64,
+    pub cells: HashMap<NodeIndex<u32>, FlowCell>,
+}
+
+// radius is walking distance between polygon centers, as in reachable_polys
+pub fn build(map_id: i64, map: &mesh_geo::Map, target: (i64, i64), radius: u64) -> FlowField {
+    let g = mesh_geo::find_closest_node(&map.graph, target.0, target.1);
+    let visited = mesh_geo::dijkstra_bounded_by(
+        &map.graph,
+        &vec![g],
+        radius,
+        |a, b, _| mesh_geo::center_dist(&map.graph[a].1, &map.graph[b].1),
+        |_| false,
+    );
+
+    let mut cells = HashMap::new();
+    for (node, (_, parent)) in &visited {
+        let portal = match parent {
+            Some(p) => match map.graph.find_edge(*node, *p) {
+                Some(e) => Some((map.graph[e]).1),
+                None => None,
+            },
+            None => None,
+        };
+        cells.insert(
+            *node,
+            FlowCell {
+                poly: (map.graph[*node]).1.clone(),
+                portal: portal,
+            },
+        );
+    }
+
+    return FlowField {
+        map_id: map_id,
+        target: target,
+        revision: map.revision,
+        cells: cells,
+    };
+}
+
+// aim at the closest point of the portal, kept off its corners
+fn closest_on_portal(((x1, y1, _), (x2, y2, _)): Line, x: f64, y: f64) -> (f64, f64) {
+    let (ax, ay) = (x1 as f64, y1 as f64);
+    let (dx, dy) = (x2 as f64 - ax, y2 as f64 - ay);
+    let len = (dx * dx + dy * dy).sqrt();
+    if len == 0.0 {
+        return (ax, ay);
+    }
+    let margin = (30.0 / len).min(0.25);
+    let t = (((x - ax) * dx + (y - ay) * dy) / (len * len))
+        .max(margin)
+        .min(1.0 - margin);
+    return (ax + dx * t, ay + dy * t);
+}
+
+impl FlowField {
+    pub fn direction(&self, map: &mesh_geo::Map, x: i64, y: i64) -> Option<(f64, f64)> {
+        let found = if map.revision == self.revision {
+            match mesh_geo::find_node_at(map, x as f64, y as f64) {
+                Some(node) => self.cells.get(&node),
+                None => None,
+            }
+        } else {
+            // obstacles changed the graph since, so the cells are searched by their polygon
+            self.cells
+                .values()
+                .find(|c| mesh_geo::poly_contains(&c.poly, x as f64, y as f64))
+        };
+        let cell = match found {
+            Some(cell) => cell,
+            None => return None,
+        };
+
+        let goal = match cell.portal {
+            Some(portal) => closest_on_portal(portal, x as f64, y as f64),
+            None => (self.target.0 as f64, self.target.1 as f64),
+        };
+        let (dx, dy) = (goal.0 - x as f64, goal.1 - y as f64);
+        let len = (dx * dx + dy * dy).sqrt();
+        if len == 0.0 {
+            return Some((0.0, 0.0));
+        }
+        return Some((dx / len, dy / len));
+    }
+}
@@ -71,7 +71,103 @@ pub fn segment_intersects(aa: (f64, f64, f32), bb: (f64, f64, f32), cc: (f64, f6
 
 
 
+// twice the signed area of a, b, c, positive when c is right of a->b
+fn triarea2(a: (f64, f64, f32), b: (f64, f64, f32), c: (f64, f64, f32)) -> f64 {
+    let ax = b.0 - a.0;
+    let ay = b.1 - a.1;
+    let bx = c.0 - a.0;
+    let by = c.1 - a.1;
+    return bx * ay - ax * by;
+}
+
+fn vequal(a: (f64, f64, f32), b: (f64, f64, f32)) -> bool {
+    return dist(a, b) < 0.001;
+}
+
+// portal as (left, right) seen from a point inside the polygon it is left from
+pub fn orient_portal(center: (i64, i64), portal: ((i64, i64, f32), (i64, i64, f32))) -> ((i64, i64, f32), (i64, i64, f32)) {
+    let (a, b) = portal;
+    let c = (center.0 as f64, center.1 as f64, 0.0);
+    if ccw((a.0 as f64, a.1 as f64, a.2), (b.0 as f64, b.1 as f64, b.2), c) > 0.0 {
+        return (b, a);
+    }
+    return (a, b);
+}
+
+// simple stupid funnel over (left, right) portals, gives the shortest path inside the corridor
 pub fn string_pull(s: (i64, i64, f32), e: (i64, i64, f32), edgs: Vec<((i64, i64, f32), (i64, i64, f32))>) -> Vec<(i64, i64, f32)>
+{
+    let mut portals: Vec<((f64, f64, f32), (f64, f64, f32))> = edgs.iter().map(|((x, y, z), (x1, y1, z1))|{
+        return ((*x as f64, *y as f64, *z), (*x1 as f64, *y1 as f64, *z1));
+    }).collect();
+    portals.insert(0, ((s.0 as f64, s.1 as f64, s.2), (s.0 as f64, s.1 as f64, s.2)));
+    portals.insert(portals.len(), ((e.0 as f64, e.1 as f64, e.2), (e.0 as f64, e.1 as f64, e.2)));
+
+    let mut portal_apex = portals[0].0;
+    let mut portal_left = portals[0].0;
+    let mut portal_right = portals[0].1;
+    let mut left_index = 0;
+    let mut right_index = 0;
+    let mut results: Vec<(f64, f64, f32)> = vec![];
+
+    let mut i = 1;
+    while i < portals.len() {
+        let (left, right) = portals[i];
+
+        // Update right vertex.
+        if triarea2(portal_apex, portal_right, right) <= 0.0 {
+            if vequal(portal_apex, portal_right) || triarea2(portal_apex, portal_left, right) > 0.0 {
+                // Tighten the funnel.
+                portal_right = right;
+                right_index = i;
+            } else {
+                // Right over left, left becomes the new apex and the scan restarts from it.
+                portal_apex = portal_left;
+                let apex_index = left_index;
+                results.insert(results.len(), portal_apex);
+                portal_left = portal_apex;
+                portal_right = portal_apex;
+                left_index = apex_index;
+                right_index = apex_index;
+                i = apex_index + 1;
+                continue;
+            }
+        }
+
+        // Update left vertex.
+        if triarea2(portal_apex, portal_left, left) >= 0.0 {
+            if vequal(portal_apex, portal_left) || triarea2(portal_apex, portal_right, left) < 0.0 {
+                // Tighten the funnel.
+                portal_left = left;
+                left_index = i;
+            } else {
+                // Left over right, right becomes the new apex and the scan restarts from it.
+                portal_apex = portal_right;
+                let apex_index = right_index;
+                results.insert(results.len(), portal_apex);
+                portal_left = portal_apex;
+                portal_right = portal_apex;
+                left_index = apex_index;
+                right_index = apex_index;
+                i = apex_index + 1;
+                continue;
+            }
+        }
+        i += 1;
+    }
+
+    results.insert(results.len(), portals[portals.len() - 1].0);
+    results.dedup();
+
+    let result: Vec<(i64, i64, f32)> = results.iter().map(|(x, y, z)|{
+        return (*x as i64, *y as i64, *z);
+    }).collect();
+
+    return result;
+}
+
+// previous string pulling through shrunk portal midpoints, kept for comparison
+pub fn string_pull_midpoint(s: (i64, i64, f32), e: (i64, i64, f32), edgs: Vec<((i64, i64, f32), (i64, i64, f32))>) -> Vec<(i64, i64, f32)>
 {
     // convert to f64
     let mut edges: Vec<((f64, f64, f32), (f64, f64, f32))> = edgs.iter().map(|((x, y, z), (x1, y1, z1))|{
@@ -153,3 +249,66 @@ pub fn string_pull(s: (i64, i64, f32), e: (i64, i64, f32), edgs: Vec<((i64, i64,
 
     return result;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // walking up along x in [0, 100], left is the -x side
+    fn straight_portals() -> Vec<((i64, i64, f32), (i64, i64, f32))> {
+        return vec![
+            ((0, 250, 0.0), (100, 250, 0.0)),
+            ((0, 500, 0.0), (100, 500, 0.0)),
+            ((0, 750, 0.0), (100, 750, 0.0)),
+        ];
+    }
+
+    // up along x in [0, 100], then right along y in [100, 200], inner corner at (100, 100)
+    fn l_portals() -> Vec<((i64, i64, f32), (i64, i64, f32))> {
+        return vec![
+            ((0, 100, 0.0), (100, 100, 0.0)),
+            ((100, 200, 0.0), (100, 100, 0.0)),
+        ];
+    }
+
+    #[test]
+    fn straight_corridor_goes_straight_to_the_end() {
+        let path = string_pull((50, 0, 0.0), (50, 1000, 0.0), straight_portals());
+        assert_eq!(path, vec![(50, 1000, 0.0)]);
+    }
+
+    #[test]
+    fn l_corridor_turns_at_the_inner_corner() {
+        let path = string_pull((50, 0, 0.0), (350, 150, 0.0), l_portals());
+        assert_eq!(path, vec![(100, 100, 0.0), (350, 150, 0.0)]);
+    }
+
+    #[test]
+    fn apex_restarts_on_a_shared_vertex() {
+        // a fan of triangles around the inner corner, every portal shares it as right
+        let portals = vec![
+            ((0, 100, 0.0), (100, 100, 0.0)),
+            ((0, 200, 0.0), (100, 100, 0.0)),
+            ((100, 200, 0.0), (100, 100, 0.0)),
+            ((200, 200, 0.0), (100, 100, 0.0)),
+        ];
+        let path = string_pull((50, 0, 0.0), (350, 150, 0.0), portals);
+        assert_eq!(path, vec![(100, 100, 0.0), (350, 150, 0.0)]);
+    }
+
+    #[test]
+    fn orient_portal_fixes_reversed_winding() {
+        let portal = ((0, 100, 0.0), (100, 100, 0.0));
+        let reversed = ((100, 100, 0.0), (0, 100, 0.0));
+        assert_eq!(orient_portal((50, 50), portal), portal);
+        assert_eq!(orient_portal((50, 50), reversed), portal);
+
+        // the L corridor with every portal flipped still pulls to the inner corner
+        let centers = vec![(50, 50), (50, 150)];
+        let portals = l_portals().into_iter().zip(centers).map(|((a, b), c)|{
+            return orient_portal(c, (b, a));
+        }).collect();
+        let path = string_pull((50, 0, 0.0), (350, 150, 0.0), portals);
+        assert_eq!(path, vec![(100, 100, 0.0), (350, 150, 0.0)]);
+    }
+}
@@ -5,115 +5,264 @@ use std::collections::HashMap;
 #[path = "algebra.rs"] mod algebra;
 
 
-pub fn generate_heated_map(friends: &Vec<((i32, i32, f32), i32)>, foes: &Vec<((i32, i32, f32), i32)>) -> Vec<(Polygon<f64>, i32)>{
-
-    let mut maps: Vec<(Polygon<f64>, i32)> = vec![];
-    for f in friends{
-        let x1 = ((f.0).0 - f.1) as f64;
-        let x2 = ((f.0).0 + f.1) as f64;        
-        let y1 = ((f.0).1 - f.1) as f64;
-        let y2 = ((f.0).1 + f.1) as f64;
-
-        let poly = Polygon::new(LineString(vec![Coordinate{x: x1, y: y1}, 
-                                                Coordinate{x: x1, y: y2},
-                                                Coordinate{x: x2, y: y2},
-                                                Coordinate{x: x2, y: y1},
-                                                Coordinate{x: x1, y: y1}]), vec![]);
-        maps.push((poly, 1));
-    }
-
-    for f in foes{
-        let x1 = ((f.0).0 - f.1) as f64;
-        let x2 = ((f.0).0 + f.1) as f64;        
-        let y1 = ((f.0).1 - f.1) as f64;
-        let y2 = ((f.0).1 + f.1) as f64;
-
-        let poly = Polygon::new(LineString(vec![Coordinate{x: x1, y: y1}, 
-                                                Coordinate{x: x1, y: y2},
-                                                Coordinate{x: x2, y: y2},
-                                                Coordinate{x: x2, y: y1},
-                                                Coordinate{x: x1, y: y1}]), vec![]);
-        maps.push((poly, -1));
-    }
-
-    // merge all
-    let mut processed = true;
-    while processed==true{
-        processed = false;
-
-        for i_poly in 0..maps.len()-1{
-            for j_poly in (i_poly+1)..maps.len(){
-
-                // check if intersect
-                let res = (maps[i_poly].0).intersection(&(maps[j_poly].0), 1.);                
-
-                let mut intersections = 0;
-                for r in res{
-                    intersections = intersections + 1;
-                    maps.push((r.clone(), maps[i_poly].1 + maps[j_poly].1));   
-
-                    let res1 = (maps[i_poly].0).difference(&r, 1.);
-                    for rr in res1{
-                        maps.push((rr.clone(), maps[i_poly].1));
-                    }
-
-                    let res2 = (maps[j_poly].0).difference(&r, 1.);
-                    for rr in res2{
-                        maps.push((rr.clone(), maps[j_poly].1));
-                    }
-                }
-
-                if intersections == 0{continue;}
-
-                maps.remove(j_poly);
-                maps.remove(i_poly);
-                processed = true;
-                break;
-            }
-            if processed==true{
-                break;
+// sides of the polygon an influence circle is approximated with
+const CIRCLE_SEGMENTS: usize = 16;
+
+// most rings a source falls off in, every ring is another disc to clip
+pub const MAX_RINGS: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct HeatSource {
+    pub pos: (i32, i32, f32),
+    pub radius: f64,
+    // heat at the center, positive for friends and negative for foes
+    pub weight: f64,
+    // steps the heat falls off in between the center and the radius
+    pub rings: usize,
+}
+
+// how heat changes the cost of crossing a polygon, danger is the negated heat so foes are positive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeatCost {
+    // 1 + factor * danger, never cheaper than MIN_COST_MUL
+    Linear(f64),
+    // base^danger, clamped between 1/cap and cap
+    Exponential(f64, f64),
+    // linear with the factor, impassable once the danger of either side of an edge is above the threshold
+    Threshold(f64, f64),
+}
+
+// the default keeps the old 100^danger behaviour for a couple of foes, but bounded
+impl Default for HeatCost {
+    fn default() -> HeatCost {
+        return HeatCost::Exponential(100.0, 10000.0);
+    }
+}
+
+const MIN_COST_MUL: f64 = 0.01;
+
+// multiplier for the edge length, None when the edge can not be crossed
+pub fn heat_cost_mul(cost: &HeatCost, heat: f64) -> Option<f64>{
+    let danger = -heat;
+    match *cost{
+        HeatCost::Linear(factor) => {
+            return Some((1.0 + factor * danger).max(MIN_COST_MUL));
+        }
+        HeatCost::Exponential(base, cap) => {
+            let cap = cap.max(1.0);
+            return Some(base.powf(danger).max(1.0 / cap).min(cap));
+        }
+        HeatCost::Threshold(factor, threshold) => {
+            if danger > threshold{
+                return None;
             }
+            return Some((1.0 + factor * danger).max(MIN_COST_MUL));
+        }
+    }
+}
+
+// multiplier ignoring the threshold, for walking out of the impassable area the unit stands in
+pub fn heat_escape_mul(cost: &HeatCost, heat: f64) -> f64{
+    match *cost{
+        HeatCost::Threshold(factor, _) => return (1.0 - factor * heat).max(MIN_COST_MUL),
+        _ => return heat_cost_mul(cost, heat).unwrap_or(1.0),
+    }
+}
+
+// one disc per ring, stacked so the heat adds up towards the center
+pub fn influence_polygons(source: &HeatSource) -> Vec<(Polygon<f64>, f64)>{
+    let rings = std::cmp::max(source.rings, 1);
+    let mut discs = vec![];
+    for k in 1..(rings + 1){
+        let r = source.radius * k as f64 / rings as f64;
+        let mut coords = vec![];
+        for i in 0..CIRCLE_SEGMENTS{
+            let a = 2.0 * std::f64::consts::PI * i as f64 / CIRCLE_SEGMENTS as f64;
+            coords.push(Coordinate{x: ((source.pos.0 as f64) + r * a.cos()).round(),
+                                   y: ((source.pos.1 as f64) + r * a.sin()).round()});
+        }
+        coords.push(coords[0]);
+        discs.push((Polygon::new(LineString(coords), vec![]), source.weight / rings as f64));
+    }
+    return discs;
+}
+
+// heat of the source at a point, the sum of the rings covering it
+pub fn heat_at(source: &HeatSource, x: f64, y: f64) -> f64{
+    let d = ((source.pos.0 as f64 - x).powi(2) + (source.pos.1 as f64 - y).powi(2)).sqrt();
+    if d > source.radius{
+        return 0.0;
+    }
+    let rings = std::cmp::max(source.rings, 1);
+    let inner = (d * rings as f64 / source.radius).ceil().max(1.0) as usize;
+    return source.weight * (rings - inner + 1) as f64 / rings as f64;
+}
+
+// shoelace area of the exterior minus the holes
+pub fn polygon_area(poly: &Polygon<f64>) -> f64{
+    let ring_area = |ring: &LineString<f64>| -> f64{
+        let pts = &ring.0;
+        let mut sum = 0.0;
+        for i in 0..pts.len(){
+            let j = (i + 1) % pts.len();
+            sum = sum + pts[i].x * pts[j].y - pts[j].x * pts[i].y;
         }
+        return (sum / 2.0).abs();
+    };
+    let mut area = ring_area(poly.exterior());
+    for hole in poly.interiors(){
+        area = area - ring_area(hole);
+    }
+    return area;
+}
 
+fn bounds(poly: &Polygon<f64>) -> ((f64, f64), (f64, f64)){
+    let mut min = (std::f64::MAX, std::f64::MAX);
+    let mut max = (std::f64::MIN, std::f64::MIN);
+    for c in &poly.exterior().0{
+        min = (min.0.min(c.x), min.1.min(c.y));
+        max = (max.0.max(c.x), max.1.max(c.y));
     }
+    return (min, max);
+}
+
+fn bounds_overlap(a: ((f64, f64), (f64, f64)), b: ((f64, f64), (f64, f64))) -> bool{
+    return (a.0).0 <= (b.1).0 && (b.0).0 <= (a.1).0 && (a.0).1 <= (b.1).1 && (b.0).1 <= (a.1).1;
+}
 
-    maps.retain(|x| x.1!=0);
-    return maps;
+// adds the regions one by one onto disjoint faces: the part of a face under the region gets the
+// region heat added, the rest keeps its heat; with keep_outside the region parts outside
+// every face become new faces, otherwise the faces bound the result
+pub fn overlay(faces: Vec<(Polygon<f64>, f64)>, regions: &Vec<(Polygon<f64>, f64)>, keep_outside: bool) -> Vec<(Polygon<f64>, f64)>{
+    let mut faces = faces;
+    for (region, heat) in regions{
+        let region_bounds = bounds(region);
+        let mut outside = MultiPolygon(vec![region.clone()]);
+        let mut next: Vec<(Polygon<f64>, f64)> = vec![];
+        for (face, face_heat) in faces{
+            if !bounds_overlap(region_bounds, bounds(&face)){
+                next.push((face, face_heat));
+                continue;
+            }
+            let inside = face.intersection(region, 1.);
+            if inside.0.len() == 0{
+                next.push((face, face_heat));
+                continue;
+            }
+            for p in inside{
+                next.push((p, face_heat + heat));
+            }
+            for p in face.difference(region, 1.){
+                next.push((p, face_heat));
+            }
+            if keep_outside{
+                outside = outside.difference(&face, 1.);
+            }
+        }
+        if keep_outside{
+            for p in outside{
+                next.push((p, *heat));
+            }
+        }
+        // clipping leaves slivers on shared edges
+        next.retain(|f| polygon_area(&f.0) >= 1.0);
+        faces = next;
+    }
+    return faces;
 }
 
 pub fn generate_heat_map_layout(
     layouts: &Vec<Polygon<f64>>, 
-    friends: &Vec<((i32, i32, f32), i32)>, 
-    foes: &Vec<((i32, i32, f32), i32)>
-) -> Vec<(Polygon<f64>, i32)>{
+    sources: &Vec<HeatSource>
+) -> Vec<(Polygon<f64>, f64)>{
+
+    let mut regions: Vec<(Polygon<f64>, f64)> = vec![];
+    for s in sources{
+        regions.extend(influence_polygons(s));
+    }
 
-    // generate heat map 
-    let maps = generate_heated_map(friends, foes);
-    let mut polys: Vec<(Polygon<f64>, i32)> = vec![];
+    let mut polys: Vec<(Polygon<f64>, f64)> = vec![];
     for p in layouts{
-        polys.push((p.clone(), 0));        
+        polys.push((p.clone(), 0.0));        
     }
+    return overlay(polys, &regions, false);
+}
 
-    let mut intersects_cnt = 0;
-    for m in &maps{
-        for i in (0..polys.len()).rev(){
-            // check intersect
-            let res = (polys[i].0).intersection(&m.0, 1.);
-            for r in res{
-                intersects_cnt = intersects_cnt + 1;
-            }
-            if intersects_cnt == 0 {continue;}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_cost_is_bounded_exponential() {
+        // 100^danger, capped at 10000 either way
+        assert_eq!(HeatCost::default(), HeatCost::Exponential(100.0, 10000.0));
+        assert_eq!(heat_cost_mul(&HeatCost::default(), 0.0), Some(1.0));
+        assert_eq!(heat_cost_mul(&HeatCost::default(), -1.0), Some(100.0));
+        assert_eq!(heat_cost_mul(&HeatCost::default(), -50.0), Some(10000.0));
+        assert_eq!(heat_cost_mul(&HeatCost::default(), 50.0), Some(0.0001));
+    }
+
+    #[test]
+    fn linear_cost_has_a_floor() {
+        assert_eq!(heat_cost_mul(&HeatCost::Linear(2.0), -1.0), Some(3.0));
+        assert_eq!(heat_cost_mul(&HeatCost::Linear(2.0), 10.0), Some(MIN_COST_MUL));
+    }
 
-            let res1 = (polys[i].0).difference(&m.0, 1.);
-            for r in res1{
-                polys.push((r.clone(), polys[i].1));
-            }            
-            polys.remove(i);
+    #[test]
+    fn threshold_cost_blocks_above_threshold() {
+        assert_eq!(heat_cost_mul(&HeatCost::Threshold(1.0, 1.5), -1.0), Some(2.0));
+        assert_eq!(heat_cost_mul(&HeatCost::Threshold(1.0, 1.5), -2.0), None);
+        // walking out ignores the threshold but still pays for the danger
+        assert_eq!(heat_escape_mul(&HeatCost::Threshold(1.0, 1.5), -2.0), 3.0);
+        assert_eq!(heat_escape_mul(&HeatCost::Linear(2.0), -1.0), 3.0);
+    }
+
+    fn square(x: f64, y: f64, size: f64) -> Polygon<f64> {
+        return Polygon::new(
+            LineString(vec![
+                Coordinate { x: x, y: y },
+                Coordinate { x: x + size, y: y },
+                Coordinate { x: x + size, y: y + size },
+                Coordinate { x: x, y: y + size },
+                Coordinate { x: x, y: y },
+            ]),
+            vec![],
+        );
+    }
+
+    fn area_with_heat(faces: &Vec<(Polygon<f64>, f64)>, heat: f64) -> f64 {
+        return faces.iter().filter(|f| (f.1 - heat).abs() < 1e-9).map(|f| polygon_area(&f.0)).sum();
+    }
+
+    #[test]
+    fn overlay_faces_are_disjoint_and_cover_the_input() {
+        let regions = vec![
+            (square(0., 0., 100.), 1.0),
+            (square(50., 50., 100.), 2.0),
+            (square(25., 25., 50.), -1.0),
+        ];
+        let faces = overlay(vec![], &regions, true);
+        for i in 0..faces.len() {
+            for j in (i + 1)..faces.len() {
+                let common: f64 = faces[i].0.intersection(&faces[j].0, 1.).into_iter().map(|p| polygon_area(&p)).sum();
+                assert!(common < 1.0);
+            }
         }
+        let covered: f64 = faces.iter().map(|f| polygon_area(&f.0)).sum();
+        assert!((covered - 17500.0).abs() < 1.0);
+        assert!((area_with_heat(&faces, 1.0) - 5625.0).abs() < 1.0);
+        assert!((area_with_heat(&faces, 0.0) - 1875.0).abs() < 1.0);
+        assert!((area_with_heat(&faces, 3.0) - 1875.0).abs() < 1.0);
+        assert!((area_with_heat(&faces, 2.0) - 8125.0).abs() < 1.0);
     }
 
-    for m in &maps{
-        polys.push((m.0.clone(), m.1));
+    #[test]
+    fn overlay_keeps_to_the_layout() {
+        // the layout bounds the result and untouched polygons are kept as they are
+        let layout = vec![(square(0., 0., 100.), 0.0), (square(200., 0., 100.), 0.0)];
+        let faces = overlay(layout, &vec![(square(50., 50., 100.), 1.0)], false);
+        assert!((area_with_heat(&faces, 1.0) - 2500.0).abs() < 1.0);
+        assert!((area_with_heat(&faces, 0.0) - 17500.0).abs() < 1.0);
+        assert!(faces.iter().any(|f| f.0 == square(200., 0., 100.)));
     }
-    return polys;
 }
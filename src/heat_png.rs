@@ -0,0 +1,92 @@
+use std::fs;
+use std::io;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for b in data {
+        crc = crc ^ (*b as u32);
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    return !crc;
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for d in data {
+        a = (a + *d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    return (b << 16) | a;
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let mut body = kind.to_vec();
+    body.extend_from_slice(data);
+    out.extend_from_slice(&body);
+    out.extend_from_slice(&crc32(&body).to_be_bytes());
+}
+
+// zlib stream with stored, uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(65535).collect();
+    if blocks.len() == 0 {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        out.push(if i == blocks.len() - 1 { 1 } else { 0 });
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    return out;
+}
+
+// friends green, foes red, brighter with more heat; off the navmesh (NaN) is black and
+// walkable cells without heat are grey
+fn heat_color(v: f32, max: f32) -> [u8; 3] {
+    if v.is_nan() {
+        return [0, 0, 0];
+    }
+    let t = (v.abs() / max).min(1.0);
+    let c = (64.0 + 191.0 * t) as u8;
+    let g = (64.0 * (1.0 - t)) as u8;
+    if v > 0.0 {
+        return [g, c, g];
+    }
+    if v < 0.0 {
+        return [c, g, g];
+    }
+    return [64, 64, 64];
+}
+
+// writes a rasterised heat grid (see mesh_geo::rasterize_heat) as an RGB png, lowest y on top
+pub fn write_heat_png(path: &str, cells: usize, values: &Vec<f32>) -> io::Result<()> {
+    let max = values.iter().filter(|v| !v.is_nan()).fold(0.0f32, |m, v| m.max(v.abs())).max(0.0001);
+    let mut raw = Vec::with_capacity(cells * (cells * 3 + 1));
+    for row in 0..cells {
+        // no filter
+        raw.push(0);
+        for col in 0..cells {
+            raw.extend_from_slice(&heat_color(values[row * cells + col], max));
+        }
+    }
+
+    let mut header = vec![];
+    header.extend_from_slice(&(cells as u32).to_be_bytes());
+    header.extend_from_slice(&(cells as u32).to_be_bytes());
+    // 8 bit RGB, deflate, no filter, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut png, b"IEND", &[]);
+    return fs::write(path, png);
+}
@@ -0,0 +1,281 @@
+use crate::mesh_geo;
+use geo_types::Polygon;
+use lazy_static::lazy_static;
+use petgraph::graph::NodeIndex;
+use petgraph::Graph;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+// decayed sources weaker than this are forgotten
+const MIN_WEIGHT: f64 = 0.01;
+
+// spacing of the samples when summing heat along a path
+const PATH_SAMPLE_STEP: f64 = 50.0;
+
+// heat sources by id, with the heated layout of each tile they touch
+pub struct InfluenceLayer {
+    // map revision the layout was clipped against
+    revision: u64,
+    // every source with the tiles it touches, found once when it is set
+    sources: HashMap<u64, (mesh_geo::HeatSource, Vec<usize>)>,
+    // time for the source weights to halve, 0 keeps them forever
+    half_life: f64,
+    // tiles missing here are clipped again the next time they are used
+    tiles: HashMap<usize, Vec<(Polygon<f64>, f64)>>,
+    // changes on every edit, tiles clipped outside the lock are only kept if it did not move
+    version: u64,
+}
+
+lazy_static! {
+    static ref LAYERS: RwLock<HashMap<(i64, String), InfluenceLayer>> = RwLock::new(HashMap::new());
+}
+
+// shared by all layers, so a layer cleared and made again never repeats a version
+static VERSION: AtomicU64 = AtomicU64::new(0);
+
+fn source_tiles(map: &mesh_geo::Map, source: &mesh_geo::HeatSource) -> Vec<usize> {
+    return mesh_geo::get_around_tiles(map, source.pos.0 as i64, source.pos.1 as i64, source.radius.ceil() as i64 + 1);
+}
+
+impl InfluenceLayer {
+    fn new(revision: u64) -> InfluenceLayer {
+        return InfluenceLayer {
+            revision: revision,
+            sources: HashMap::new(),
+            half_life: 0.0,
+            tiles: HashMap::new(),
+            version: VERSION.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    fn touch(&mut self) {
+        self.version = VERSION.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // the layout and the source tiles are redone when obstacles changed the map polygons
+    fn sync(&mut self, map: &mesh_geo::Map) {
+        if self.revision != map.revision {
+            self.revision = map.revision;
+            self.tiles.clear();
+            for (source, tiles) in self.sources.values_mut() {
+                *tiles = source_tiles(map, source);
+            }
+            self.touch();
+        }
+    }
+
+    fn tile_sources(&self, tile: usize) -> Vec<mesh_geo::HeatSource> {
+        let mut sources = vec![];
+        for (source, tiles) in self.sources.values() {
+            if tiles.contains(&tile) {
+                sources.push(source.clone());
+            }
+        }
+        return sources;
+    }
+
+    fn heat_at(&self, x: f64, y: f64) -> f64 {
+        return self.sources.values().map(|(s, _)| mesh_geo::heat_at(s, x, y)).sum();
+    }
+
+    // decays every source by dt, the same factor scales the clipped faces in place,
+    // only the tiles of forgotten sources are clipped again
+    fn step(&mut self, map: &mesh_geo::Map, dt: f64) {
+        if self.half_life <= 0.0 || self.sources.len() == 0 || !dt.is_finite() || dt < 0.0 {
+            return;
+        }
+        self.sync(map);
+        let factor = (0.5 as f64).powf(dt / self.half_life);
+        for (source, _) in self.sources.values_mut() {
+            source.weight = source.weight * factor;
+        }
+        for layout in self.tiles.values_mut() {
+            for face in layout.iter_mut() {
+                face.1 = face.1 * factor;
+            }
+        }
+
+        let mut dirty = vec![];
+        for (source, tiles) in self.sources.values() {
+            if source.weight.abs() < MIN_WEIGHT {
+                dirty.extend(tiles.iter().cloned());
+            }
+        }
+        self.sources.retain(|_, (s, _)| s.weight.abs() >= MIN_WEIGHT);
+        for tile in dirty {
+            self.tiles.remove(&tile);
+        }
+        self.touch();
+    }
+}
+
+// adds the source or moves it when the id is already in the layer
+pub fn set_source(map_id: i64, map: &mesh_geo::Map, layer: &str, id: u64, source: mesh_geo::HeatSource) {
+    let mut layers = LAYERS.write().unwrap();
+    let layer = layers
+        .entry((map_id, layer.to_string()))
+        .or_insert(InfluenceLayer::new(map.revision));
+    layer.sync(map);
+    let tiles = source_tiles(map, &source);
+    let mut dirty = tiles.clone();
+    match layer.sources.insert(id, (source, tiles)) {
+        Some((_, prev_tiles)) => dirty.extend(prev_tiles),
+        None => (),
+    }
+    for tile in dirty {
+        layer.tiles.remove(&tile);
+    }
+    layer.touch();
+}
+
+pub fn remove_source(map_id: i64, map: &mesh_geo::Map, layer: &str, id: u64) -> bool {
+    let mut layers = LAYERS.write().unwrap();
+    let layer = match layers.get_mut(&(map_id, layer.to_string())) {
+        Some(layer) => layer,
+        None => return false,
+    };
+    layer.sync(map);
+    match layer.sources.remove(&id) {
+        Some((_, prev_tiles)) => {
+            for tile in prev_tiles {
+                layer.tiles.remove(&tile);
+            }
+            layer.touch();
+            return true;
+        }
+        None => return false,
+    }
+}
+
+pub fn set_half_life(map_id: i64, map: &mesh_geo::Map, layer: &str, half_life: f64) {
+    let mut layers = LAYERS.write().unwrap();
+    let layer = layers
+        .entry((map_id, layer.to_string()))
+        .or_insert(InfluenceLayer::new(map.revision));
+    layer.half_life = half_life;
+}
+
+// one server tick for every layer of the map
+pub fn step(map_id: i64, map: &mesh_geo::Map, dt: f64) {
+    let mut layers = LAYERS.write().unwrap();
+    for ((id, _), layer) in layers.iter_mut() {
+        if *id == map_id {
+            layer.step(map, dt);
+        }
+    }
+}
+
+pub fn heat_at(map_id: i64, layer: &str, x: f64, y: f64) -> Option<f64> {
+    let layers = LAYERS.read().unwrap();
+    match layers.get(&(map_id, layer.to_string())) {
+        Some(layer) => return Some(layer.heat_at(x, y)),
+        None => return None,
+    }
+}
+
+// heat summed over the path length, and the strongest heat on the way
+pub fn heat_along(map_id: i64, layer: &str, points: &Vec<(f64, f64)>) -> Option<(f64, f64)> {
+    let layers = LAYERS.read().unwrap();
+    let layer = match layers.get(&(map_id, layer.to_string())) {
+        Some(layer) => layer,
+        None => return None,
+    };
+    let mut total = 0.0;
+    let mut peak: f64 = 0.0;
+    for i in 1..points.len() {
+        let (a, b) = (points[i - 1], points[i]);
+        let len = (b.0 - a.0).hypot(b.1 - a.1);
+        let n = std::cmp::max((len / PATH_SAMPLE_STEP).ceil() as usize, 1);
+        for k in 0..n {
+            // middle of each sample span
+            let t = (k as f64 + 0.5) / n as f64;
+            let heat = layer.heat_at(a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+            total = total + heat * len / n as f64;
+            if heat.abs() > peak.abs() {
+                peak = heat;
+            }
+        }
+    }
+    return Some((total, peak));
+}
+
+// the layer rasterised around center, see mesh_geo::rasterize_heat
+pub fn raster(
+    map_id: i64,
+    map: &mesh_geo::Map,
+    layer: &str,
+    center: (f64, f64),
+    cells: usize,
+    cell_size: f64,
+) -> Option<Vec<f32>> {
+    let layers = LAYERS.read().unwrap();
+    let layer = match layers.get(&(map_id, layer.to_string())) {
+        Some(layer) => layer,
+        None => return None,
+    };
+    let sources: Vec<mesh_geo::HeatSource> = layer.sources.values().map(|(s, _)| s.clone()).collect();
+    return Some(mesh_geo::rasterize_heat(map, &sources, center, cells, cell_size));
+}
+
+pub fn clear_layer(map_id: i64, layer: &str) -> bool {
+    return LAYERS.write().unwrap().remove(&(map_id, layer.to_string())).is_some();
+}
+
+// same as create_heatmap_graph, with the heat taken from the layer
+pub fn layer_heatmap_graph(
+    map_id: i64,
+    map: &mesh_geo::Map,
+    layer: &str,
+    from: (i64, i64),
+    cost: &mesh_geo::HeatCost,
+) -> Option<(Graph<usize, (f64, mesh_geo::Line)>, Vec<mesh_geo::PrePolyWithHeat>, Vec<NodeIndex>)> {
+    let tiles = mesh_geo::get_around_tiles(&map, from.0, from.1, 2520);
+    if tiles.len() == 0 {
+        return None;
+    }
+
+    let key = (map_id, layer.to_string());
+
+    // cached tiles and the sources of the missing ones, under a short lock
+    let (version, mut heated_polys, missing, sources) = {
+        let mut layers = LAYERS.write().unwrap();
+        let layer = match layers.get_mut(&key) {
+            Some(layer) => layer,
+            None => return None,
+        };
+        layer.sync(map);
+        let mut heated_polys = vec![];
+        let mut missing = vec![];
+        let mut sources = vec![];
+        for tile in &tiles {
+            let tile_sources = layer.tile_sources(*tile);
+            match layer.tiles.get(tile) {
+                Some(layout) => heated_polys.extend(layout.iter().cloned()),
+                None => missing.push((*tile, tile_sources.clone())),
+            }
+            sources.extend(tile_sources);
+        }
+        (layer.version, heated_polys, missing, sources)
+    };
+
+    // clipping runs without the lock, so other layers and maps are not held up
+    let mut clipped = vec![];
+    for (tile, tile_sources) in missing {
+        let layout = mesh_geo::generate_heat_map_layout(&map.polygons[tile], &tile_sources);
+        heated_polys.extend(layout.iter().cloned());
+        clipped.push((tile, layout));
+    }
+    if clipped.len() > 0 {
+        let mut layers = LAYERS.write().unwrap();
+        match layers.get_mut(&key) {
+            Some(layer) if layer.version == version => {
+                for (tile, layout) in clipped {
+                    layer.tiles.insert(tile, layout);
+                }
+            }
+            _ => (),
+        }
+    }
+    return Some(mesh_geo::heat_layout_graph(map, from, &tiles, &heated_polys, &sources, cost));
+}
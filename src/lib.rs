@@ -2,140 +2,152 @@ use petgraph::algo::astar;
 use petgraph::Graph;
 use rustler::{Encoder, Env, Error, Term};
 //use rustler::types::ListIterator;
-//use petgraph::graph::NodeIndex;
+use petgraph::graph::NodeIndex;
 
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 use std::fs;
 
 use std::cell::Cell;
+use std::sync::RwLock;
 use std::time::Instant;
 
 use geo_clipper::Clipper;
 use geo_types::{Coordinate, LineString, Polygon};
 use rustler::schedule::SchedulerFlags;
+use rustler::ResourceArc;
+use rustler::OwnedBinary;
 use serde::{Deserialize, Serialize};
 
+mod crowd;
+mod flow_field;
 mod funnel;
+mod influence;
+mod local_graph;
 mod mesh_geo;
+mod movement;
+mod moving_path;
+mod sampling;
+mod smoothing;
+mod tactical;
 #[path = "algebra.rs"] mod algebra;
 
 lazy_static! {
     //Level 40 quest
-    static ref MAP_91: mesh_geo::Map = {
+    static ref MAP_91: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Elven_Ruins_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
     //Tutorial
-    static ref MAP_92: mesh_geo::Map = {
+    static ref MAP_92: RwLock<mesh_geo::Map> = {
 
         let entries = vec!["../assets/mapdata/dungeon/Elven_Ruins_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
 
-    static ref MAP_1: mesh_geo::Map = {
+    static ref MAP_1: RwLock<mesh_geo::Map> = {
         let entries = fs::read_dir("../assets/mapdata/worldmap/")
             .unwrap()
             .map(|res| res.unwrap().path().to_str().unwrap().to_string())
             .collect::<Vec<_>>();
 
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
 
     //Noob forlore temple
-    static ref MAP_11: mesh_geo::Map = {
+    static ref MAP_11: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Forgotten_Temple_B1_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
 
     //Ant Cave
-    static ref MAP_21: mesh_geo::Map = {
+    static ref MAP_21: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Ant_Nest_B1_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_22: mesh_geo::Map = {
+    static ref MAP_22: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Ant_Nest_B2_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_23: mesh_geo::Map = {
+    static ref MAP_23: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Ant_Nest_B3_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
 
     //Dungeons
     //Cruma
-    static ref MAP_1031: mesh_geo::Map = {
+    static ref MAP_1031: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Tower_of_Cruma_B1_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_1032: mesh_geo::Map = {
+    static ref MAP_1032: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Tower_of_Cruma_B2_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_1033: mesh_geo::Map = {
+    static ref MAP_1033: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Tower_of_Cruma_B3_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_1034: mesh_geo::Map = {
+    static ref MAP_1034: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Tower_of_Cruma_B4_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_1035: mesh_geo::Map = {
+    static ref MAP_1035: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Tower_of_Cruma_B5_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_1036: mesh_geo::Map = {
+    static ref MAP_1036: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Tower_of_Cruma_B6_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_1037: mesh_geo::Map = {
+    static ref MAP_1037: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Tower_of_Cruma_B7_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
     //Level 40
-    static ref MAP_2001: mesh_geo::Map = {
+    static ref MAP_2001: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Battle_Island_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_2002: mesh_geo::Map = {
+    static ref MAP_2002: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Destroyed_Castle_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
     //Level 45
-    static ref MAP_1041: mesh_geo::Map = {
+    static ref MAP_1041: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Nest_of_Antaras_B1_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_1042: mesh_geo::Map = {
+    static ref MAP_1042: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Nest_of_Antaras_B2_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_1043: mesh_geo::Map = {
+    static ref MAP_1043: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Nest_of_Antaras_B3_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_1044: mesh_geo::Map = {
+    static ref MAP_1044: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Nest_of_Antaras_B4_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_1045: mesh_geo::Map = {
+    static ref MAP_1045: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Nest_of_Antaras_B5_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
-    static ref MAP_1046: mesh_geo::Map = {
+    static ref MAP_1046: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Nest_of_Antaras_B6_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
     //Event
-    static ref MAP_5023: mesh_geo::Map = {
+    static ref MAP_5023: RwLock<mesh_geo::Map> = {
         let entries = vec!["../assets/mapdata/dungeon/Christmas_Island_NavTile.uexp".to_string()];
-        return mesh_geo::files_to_map(entries, "mapdata/map1.bin");
+        return RwLock::new(mesh_geo::files_to_map(entries, "mapdata/map1.bin"));
     };
 
 }
 
-fn map_by_map_id(map_id: i64) -> &'static mesh_geo::Map {
-    let map: &mesh_geo::Map = match map_id {
+fn known_map(map_id: i64) -> Option<&'static RwLock<mesh_geo::Map>> {
+    let map: &RwLock<mesh_geo::Map> = match map_id {
         1 => &MAP_1,
         91 => &MAP_91,
         92 => &MAP_92,
@@ -159,9 +171,17 @@ fn map_by_map_id(map_id: i64) -> &'static mesh_geo::Map {
         1045 => &MAP_1045,
         1046 => &MAP_1046,
         5023 => &MAP_5023,
-        _ => &MAP_1046,
+        _ => return None,
     };
-    return map;
+    return Some(map);
+}
+
+// unknown ids fall back to 1046 for queries, changes go through known_map
+fn map_by_map_id(map_id: i64) -> &'static RwLock<mesh_geo::Map> {
+    match known_map(map_id) {
+        Some(map) => return map,
+        None => return &MAP_1046,
+    }
 }
 
 mod atoms {
@@ -169,6 +189,26 @@ mod atoms {
         atom ok;
         atom error;
         atom same_polygon;
+        atom unreachable;
+        atom in_range;
+        atom catmull_rom;
+        atom bezier;
+        atom valid;
+        atom off_mesh;
+        atom too_fast;
+        atom wall;
+        atom height;
+        atom linear;
+        atom exponential;
+        atom threshold;
+        atom heat;
+        atom threats;
+        atom threat_distance;
+        atom line_of_sight;
+        atom travel;
+        atom samples;
+        atom top;
+        atom seed;
         //atom __true__ = "true";
         //atom __false__ = "false";
     }
@@ -178,27 +218,78 @@ rustler::rustler_export_nifs! {
     "Elixir.GameMap",
     [
         ("path", 3, path, SchedulerFlags::DirtyCpu),
+        ("path", 4, path, SchedulerFlags::DirtyCpu),
         ("path_near", 3, path_near, SchedulerFlags::DirtyCpu),
         ("can_walk_direct", 3, is_can_walk_direct, SchedulerFlags::DirtyCpu),
+        ("raycast", 3, raycast, SchedulerFlags::DirtyCpu),
+        ("move_along_surface", 3, move_along_surface, SchedulerFlags::DirtyCpu),
+        ("validate_movement", 4, validate_movement, SchedulerFlags::DirtyCpu),
+        ("distance_to_wall", 3, distance_to_wall, SchedulerFlags::DirtyCpu),
         ("path_local", 4, path_local, SchedulerFlags::DirtyCpu),
+        ("path_local", 7, path_local, SchedulerFlags::DirtyCpu),
         ("is_walkable", 2, is_walkable, SchedulerFlags::DirtyCpu),                
         ("around_boxes", 3, around_boxes, SchedulerFlags::DirtyCpu),
+        ("around_boxes", 6, around_boxes, SchedulerFlags::DirtyCpu),
         ("heat_maps", 4, heat_maps, SchedulerFlags::DirtyCpu),        
-        ("path_heatmap", 5, path_heatmap, SchedulerFlags::DirtyCpu)
+        ("path_heatmap", 5, path_heatmap, SchedulerFlags::DirtyCpu),
+        ("path_heatmap", 6, path_heatmap, SchedulerFlags::DirtyCpu),
+        ("add_obstacle", 3, add_obstacle, SchedulerFlags::DirtyCpu),
+        ("remove_obstacle", 2, remove_obstacle, SchedulerFlags::DirtyCpu),
+        ("flow_field", 3, flow_field, SchedulerFlags::DirtyCpu),
+        ("flow_direction", 2, flow_direction, SchedulerFlags::DirtyCpu),
+        ("reachable_within", 3, reachable_within, SchedulerFlags::DirtyCpu),
+        ("reachable_within", 4, reachable_within, SchedulerFlags::DirtyCpu),
+        ("random_point", 3, random_point, SchedulerFlags::DirtyCpu),
+        ("random_point", 4, random_point, SchedulerFlags::DirtyCpu),
+        ("path_distance", 3, path_distance, SchedulerFlags::DirtyCpu),
+        ("path_distance_many", 3, path_distance_many, SchedulerFlags::DirtyCpu),
+        ("path_distance_many", 4, path_distance_many, SchedulerFlags::DirtyCpu),
+        ("nearest_by_path", 4, nearest_by_path, SchedulerFlags::DirtyCpu),
+        ("nearest_by_path", 5, nearest_by_path, SchedulerFlags::DirtyCpu),
+        ("path_in_range", 4, path_in_range, SchedulerFlags::DirtyCpu),
+        ("flee_path", 4, flee_path, SchedulerFlags::DirtyCpu),
+        ("flee_path", 5, flee_path, SchedulerFlags::DirtyCpu),
+        ("resample_path", 3, resample_path, SchedulerFlags::DirtyCpu),
+        ("smooth_path", 4, smooth_path, SchedulerFlags::DirtyCpu),
+        ("moving_path", 3, moving_path_new),
+        ("moving_path_position_at", 2, moving_path_position_at),
+        ("moving_path_remaining_distance", 2, moving_path_remaining_distance),
+        ("moving_path_progress_to", 2, moving_path_progress_to),
+        ("moving_path_set_speed", 3, moving_path_set_speed),
+        ("crowd", 1, crowd_new),
+        ("crowd", 3, crowd_new),
+        ("crowd_add_agent", 5, crowd_add_agent),
+        ("crowd_remove_agent", 2, crowd_remove_agent),
+        ("crowd_set_velocity", 3, crowd_set_velocity),
+        ("crowd_step", 2, crowd_step, SchedulerFlags::DirtyCpu),
+        ("influence_set", 4, influence_set, SchedulerFlags::DirtyCpu),
+        ("influence_remove", 3, influence_remove, SchedulerFlags::DirtyCpu),
+        ("influence_clear", 2, influence_clear, SchedulerFlags::DirtyCpu),
+        ("path_influence", 4, path_influence, SchedulerFlags::DirtyCpu),
+        ("path_influence", 5, path_influence, SchedulerFlags::DirtyCpu),
+        ("influence_half_life", 3, influence_half_life, SchedulerFlags::DirtyCpu),
+        ("influence_step", 2, influence_step, SchedulerFlags::DirtyCpu),
+        ("influence_at", 3, influence_at, SchedulerFlags::DirtyCpu),
+        ("influence_along", 3, influence_along, SchedulerFlags::DirtyCpu),
+        ("influence_raster", 5, influence_raster, SchedulerFlags::DirtyCpu),
+        ("find_tactical_position", 4, find_tactical_position, SchedulerFlags::DirtyCpu)
     ],
-    None
+    Some(on_load)
 }
 
-fn calc_path(
-    map: &mesh_geo::Map,
-    from: (i64, i64),
-    to: (i64, i64),
-) -> Option<Vec<(i64, i64, f32)>> {
+fn on_load(env: Env, _info: Term) -> bool {
+    rustler::resource_struct_init!(flow_field::FlowField, env);
+    rustler::resource_struct_init!(moving_path::MovingPath, env);
+    rustler::resource_struct_init!(crowd::Crowd, env);
+    return true;
+}
+
+fn calc_corridor(map: &mesh_geo::Map, from: (i64, i64), to: (i64, i64)) -> Option<Vec<NodeIndex>> {
     let g1 = mesh_geo::find_closest_node(&map.graph, from.0, from.1);
     let g2 = mesh_geo::find_closest_node(&map.graph, to.0, to.1);
 
     if g1.index() == g2.index() {
-        return Some(vec![]);
+        return Some(vec![g1]);
     }
 
     thread_local!(static EXPANDED: Cell<u64> = Cell::new(0));
@@ -224,32 +315,98 @@ fn calc_path(
     if p1.len() == 0 {
         return None;
     }
+    return Some(p1);
+}
 
-    let mut prevnode = p1[0];
-    let mut edges = Vec::new();
-    for node in p1 {
-        if prevnode != node {
-            for edge in map.graph.edges_connecting(prevnode, node) {
-                let (_d, l) = edge.weight();
-                edges.push(l);
+fn corridor_portals(
+    map: &mesh_geo::Map,
+    nodes: &Vec<NodeIndex>,
+) -> Vec<((i64, i64, f32), (i64, i64, f32))> {
+    let mut prevnode = nodes[0];
+    let mut edgs = Vec::new();
+    for node in nodes {
+        if prevnode != *node {
+            // left/right winding as seen from the polygon the portal is left from
+            let center = (map.graph[prevnode]).1.center;
+            for edge in map.graph.edges_connecting(prevnode, *node) {
+                let (_d, (l1, l2)) = edge.weight();
+                edgs.push(funnel::orient_portal(
+                    (center.0 as i64, center.1 as i64),
+                    (
+                        (l1.0 as i64, l1.1 as i64, l1.2),
+                        (l2.0 as i64, l2.1 as i64, l2.2),
+                    ),
+                ));
             }
         }
-        prevnode = node;
+        prevnode = *node;
     }
+    return edgs;
+}
 
-    let edgs: Vec<((i64, i64, f32), (i64, i64, f32))> = edges
-        .iter()
-        .map(|x| {
-            let (l1, l2) = x;
-            return (
-                (l1.0 as i64, l1.1 as i64, l1.2),
-                (l2.0 as i64, l2.1 as i64, l2.2),
-            );
-        })
-        .collect();
-    let to_z = get_z_from_poly(to.0 as i32, to.1 as i32, &map.graph[g2].1);
-    let re = funnel::string_pull((from.0, from.1, 0.0), (to.0, to.1, to_z), edgs);
-    return Some(re);
+fn calc_path(
+    map: &mesh_geo::Map,
+    from: (i64, i64),
+    to: (i64, i64),
+) -> Option<Vec<(i64, i64, f32)>> {
+    let p1 = calc_corridor(map, from, to)?;
+    if p1.len() == 1 {
+        return Some(vec![]);
+    }
+
+    return Some(corridor_path(map, from, to, &p1));
+}
+
+// old portal midpoint string pulling, for comparing against the funnel
+fn calc_path_midpoint(
+    map: &mesh_geo::Map,
+    from: (i64, i64),
+    to: (i64, i64),
+) -> Option<Vec<(i64, i64, f32)>> {
+    let p1 = calc_corridor(map, from, to)?;
+    if p1.len() == 1 {
+        return Some(vec![]);
+    }
+
+    let g2 = p1[p1.len() - 1];
+    let edgs = corridor_portals(map, &p1);
+    let to_z = mesh_geo::get_z_from_poly(to.0 as i32, to.1 as i32, &map.graph[g2].1);
+    return Some(funnel::string_pull_midpoint((from.0, from.1, 0.0), (to.0, to.1, to_z), edgs));
+}
+
+fn corridor_path(
+    map: &mesh_geo::Map,
+    from: (i64, i64),
+    to: (i64, i64),
+    nodes: &Vec<NodeIndex>,
+) -> Vec<(i64, i64, f32)> {
+    let g2 = nodes[nodes.len() - 1];
+    let to_z = mesh_geo::get_z_from_poly(to.0 as i32, to.1 as i32, &map.graph[g2].1);
+    if nodes.len() == 1 {
+        return vec![(to.0, to.1, to_z)];
+    }
+    let edgs = corridor_portals(map, nodes);
+    return funnel::string_pull((from.0, from.1, 0.0), (to.0, to.1, to_z), edgs);
+}
+
+fn path_length(from: (i64, i64), points: &Vec<(i64, i64, f32)>) -> u64 {
+    let mut length = 0;
+    let mut prev = from;
+    for p in points {
+        length += heur_dist(&prev, &(p.0, p.1));
+        prev = (p.0, p.1);
+    }
+    return length;
+}
+
+// walking distance along the funnelled corridor, straight line inside one polygon
+fn corridor_distance(map: &mesh_geo::Map, from: (i64, i64), to: (i64, i64), nodes: &Vec<NodeIndex>) -> u64 {
+    if nodes.len() == 1 {
+        return heur_dist(&from, &to);
+    }
+    let edgs = corridor_portals(map, nodes);
+    let re = funnel::string_pull((from.0, from.1, 0.0), (to.0, to.1, 0.0), edgs);
+    return path_length(from, &re);
 }
 
 fn path<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
@@ -258,10 +415,19 @@ fn path<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
     let map_id: i64 = args[0].decode::<i64>()?;
     let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
     let to: (i64, i64) = args[2].decode::<(i64, i64)>()?;
+    let mut midpoint = false;
+    if args.len() > 3 {
+        midpoint = args[3].decode::<bool>()?;
+    }
 
-    let map: &mesh_geo::Map = map_by_map_id(map_id);
+    let map = map_by_map_id(map_id).read().unwrap();
 
-    match calc_path(map, from, to) {
+    let res = if midpoint {
+        calc_path_midpoint(&map, from, to)
+    } else {
+        calc_path(&map, from, to)
+    };
+    match res {
         Some(res) => {
             if res.len() == 0 {
                 return Ok(atoms::same_polygon().encode(env));
@@ -282,14 +448,95 @@ fn is_can_walk_direct<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, E
     let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
     let to: (i64, i64) = args[2].decode::<(i64, i64)>()?;
 
-    let map: &mesh_geo::Map = map_by_map_id(map_id);
-    let dist = heur_dist(&from, &to);
-    if dist < 2000 && can_walk_direct(map, from, to) {
+    let map = map_by_map_id(map_id).read().unwrap();
+    if can_walk_direct(&map, from, to) {
         return Ok((atoms::ok(), 0 as u64).encode(env));
     }
     return Ok((atoms::error(), 0 as u64).encode(env));
 }
 
+fn raycast<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let path_time_start = Instant::now();
+
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    let to: (i64, i64) = args[2].decode::<(i64, i64)>()?;
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    match mesh_geo::raycast(&map, (from.0 as f64, from.1 as f64), (to.0 as f64, to.1 as f64)) {
+        Some(hit) => {
+            let (x, y, z) = hit.point;
+            let visited: Vec<u64> = hit.visited.iter().map(|n| map.graph[*n].0).collect();
+            let took = path_time_start.elapsed().as_micros();
+            return Ok((atoms::ok(), took as u64, hit.hit, (x as i64, y as i64, z), hit.normal, visited).encode(env));
+        }
+        None => return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env)),
+    }
+}
+
+fn move_along_surface<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let path_time_start = Instant::now();
+
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    let to: (i64, i64) = args[2].decode::<(i64, i64)>()?;
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    match mesh_geo::move_along_surface(&map, (from.0 as f64, from.1 as f64), (to.0 as f64, to.1 as f64)) {
+        Some(((x, y, z), nodes)) => {
+            let visited: Vec<u64> = nodes.iter().map(|n| map.graph[*n].0).collect();
+            let took = path_time_start.elapsed().as_micros();
+            return Ok((atoms::ok(), took as u64, (x as i64, y as i64, z), visited).encode(env));
+        }
+        None => return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env)),
+    }
+}
+
+fn validate_movement<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let path_time_start = Instant::now();
+
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let points: Vec<(i64, i64, f32)> = args[1].decode::<Vec<(i64, i64, f32)>>()?;
+    let max_speed: f64 = args[2].decode::<f64>()?;
+    let dt: f64 = args[3].decode::<f64>()?;
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    let res = movement::validate(&map, &points, max_speed, dt);
+    let took = path_time_start.elapsed().as_micros() as u64;
+    match res {
+        Some((idx, violation, (x, y, z))) => {
+            let reason = match violation {
+                movement::Violation::OffMesh => atoms::off_mesh(),
+                movement::Violation::TooFast => atoms::too_fast(),
+                movement::Violation::Wall => atoms::wall(),
+                movement::Violation::Height => atoms::height(),
+            };
+            return Ok((atoms::ok(), took, idx, reason, (x as i64, y as i64, z)).encode(env));
+        }
+        None => return Ok((atoms::ok(), took, atoms::valid()).encode(env)),
+    }
+}
+
+fn distance_to_wall<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let path_time_start = Instant::now();
+
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let pos: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    let max_radius: f64 = args[2].decode::<f64>()?;
+    if !max_radius.is_finite() || max_radius < 0.0 {
+        return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
+    }
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    match mesh_geo::distance_to_wall(&map, pos.0 as f64, pos.1 as f64, max_radius) {
+        Some((dist, (x, y), normal)) => {
+            let took = path_time_start.elapsed().as_micros();
+            return Ok((atoms::ok(), took as u64, dist, (x as i64, y as i64), normal).encode(env));
+        }
+        None => return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env)),
+    }
+}
+
 fn path_near<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
     let path_time_start = Instant::now();
 
@@ -297,9 +544,8 @@ fn path_near<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
     let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
     let to: (i64, i64) = args[2].decode::<(i64, i64)>()?;
 
-    let map: &mesh_geo::Map = map_by_map_id(map_id);
-    let dist = heur_dist(&from, &to);
-    if dist < 2000 && can_walk_direct(map, from, to) {
+    let map = map_by_map_id(map_id).read().unwrap();
+    if can_walk_direct(&map, from, to) {
         return Ok((atoms::ok(), 0 as u64, vec![(to.0, to.1, 0.0)]).encode(env));
     }
 
@@ -341,29 +587,7 @@ fn path_near<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
         return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
     }
 
-    let mut prevnode = p1[0];
-    let mut edges = Vec::new();
-    for node in p1 {
-        if prevnode != node {
-            for edge in map.graph.edges_connecting(prevnode, node) {
-                let (_d, l) = edge.weight();
-                edges.push(l);
-            }
-            //println!("e {:?}", edge.weight());
-        }
-        prevnode = node;
-    }
-
-    let edgs: Vec<((i64, i64, f32), (i64, i64, f32))> = edges
-        .iter()
-        .map(|x| {
-            let (l1, l2) = x;
-            return (
-                (l1.0 as i64, l1.1 as i64, l1.2),
-                (l2.0 as i64, l2.1 as i64, l2.2),
-            );
-        })
-        .collect();
+    let edgs = corridor_portals(&map, &p1);
     let re = funnel::string_pull((from.0, from.1, 0.0), (to.0, to.1, 0.0), edgs);
 
     let path_took = path_time_start.elapsed().as_micros();
@@ -376,137 +600,71 @@ fn heur_dist((tox, toy): &(i64, i64), (nx, n_y): &(i64, i64)) -> u64 {
     return ((dx * dx + dy * dy) as f32).sqrt().trunc() as u64;
 }
 
-fn get_z_from_poly(x: i32, y: i32, poly: &mesh_geo::PrePoly) -> f32 {
-    let v1 = (
-        poly.verts[0].0 as f64,
-        poly.verts[0].1 as f64,
-        poly.verts[0].2 as f64,
-    );
-    let v2 = (
-        poly.verts[1].0 as f64,
-        poly.verts[1].1 as f64,
-        poly.verts[1].2 as f64,
-    );
-    let v3 = (
-        poly.verts[2].0 as f64,
-        poly.verts[2].1 as f64,
-        poly.verts[2].2 as f64,
-    );
-
-    let a = v1.1 * (v2.2 - v3.2) + v2.1 * (v3.2 - v1.2) + v3.1 * (v1.2 - v2.2);
-    let b = v1.2 * (v2.0 - v3.0) + v2.2 * (v3.0 - v1.0) + v3.2 * (v1.0 - v2.0);
-    let c = v1.0 * (v2.1 - v3.1) + v2.0 * (v3.1 - v1.1) + v3.0 * (v1.1 - v2.1);
-    let d = v1.0 * (v2.1 * v3.2 - v3.1 * v2.2)
-        + v2.0 * (v3.1 * v1.2 - v1.1 * v3.2)
-        + v3.0 * (v1.1 * v2.2 - v2.1 * v1.2);
-
-    let z = (d - (a * x as f64) - (b * y as f64)) / c;
-    return z as f32;
-}
-
 pub fn can_walk_direct(map: &mesh_geo::Map, from: (i64, i64), to: (i64, i64)) -> bool {
-    let mut tiles = mesh_geo::get_around_tiles(&map, from.0, from.1, 2400);
-    let mut polys = vec![];
-
-    if tiles.len() == 0 {
-        return false;
-    }
-    // make polygons
-    for i_t in tiles {
-        for p in &map.polygons[i_t] {
-            polys.push(p.clone());
-        }
+    match mesh_geo::raycast(map, (from.0 as f64, from.1 as f64), (to.0 as f64, to.1 as f64)) {
+        Some(hit) => return !hit.hit,
+        None => return false,
     }
+}
 
-    // println!("can_walk_direct1={:?}", polys.len());
-
-    //merge polys
-    let mut merged_poly = true;
-    while merged_poly == true {
-        merged_poly = false;
-        for i_poly in 0..polys.len() - 1 {
-            for j_poly in (i_poly + 1)..polys.len() {
-                match mesh_geo::merge_two_polygon(&polys[i_poly], &polys[j_poly]) {
-                    Some(new_poly) => {
-                        merged_poly = true;
-                        polys.remove(j_poly);
-                        polys.remove(i_poly);
-                        polys.insert(i_poly, new_poly);
-                    }
-                    None => (),
-                }
-                if merged_poly == true {
-                    break;
-                }
-            }
-            if merged_poly == true {
-                break;
-            }
+// {x, y, z} is a 60x60 square, {x, y, z, radius} a circle and {x, y, z, half_w, half_h, angle} a box
+fn decode_obstacles<'a>(term: Term<'a>) -> Result<Vec<mesh_geo::LocalObstacle>, Error> {
+    let mut obstacles = vec![];
+    for item in term.decode::<Vec<Term<'a>>>()? {
+        let t = rustler::types::tuple::get_tuple(item)?;
+        if t.len() < 3 {
+            return Err(Error::BadArg);
         }
+        let pos = (t[0].decode::<i32>()?, t[1].decode::<i32>()?, t[2].decode::<f32>()?);
+        let obstacle = match t.len() {
+            3 => mesh_geo::player_obstacle(pos),
+            4 => mesh_geo::LocalObstacle {
+                pos: pos,
+                shape: mesh_geo::ObstacleShape::Circle(t[3].decode::<f64>()?),
+            },
+            6 => mesh_geo::LocalObstacle {
+                pos: pos,
+                shape: mesh_geo::ObstacleShape::OrientedBox(
+                    t[3].decode::<f64>()?,
+                    t[4].decode::<f64>()?,
+                    t[5].decode::<f64>()?,
+                ),
+            },
+            _ => return Err(Error::BadArg),
+        };
+        obstacles.push(obstacle);
     }
+    return Ok(obstacles);
+}
 
-    // check if line is intersect with polys lines
-    let a = (from.0 as f64, from.1 as f64);
-    let b = (to.0 as f64, to.1 as f64);
-    let mut can_walk = true;
-    for p in polys {
-        let mut points = mesh_geo::dedup_coordinates(p.exterior().clone().into_iter().collect());
-        points.push(Coordinate {
-            x: points[0].x,
-            y: points[0].y,
-        });
-        for i in 0..(points.len() - 1) {
-            let c = (points[i].x, points[i].y);
-            let d = (points[i + 1].x, points[i + 1].y);
-            if algebra::segment_intersects(a, b, c, d) > 0 {
-                can_walk = false;
-                break;
-            }
-        }
-        if can_walk == false {
-            break;
-        }
-
-        for line in p.interiors() {
-            let mut pointts = mesh_geo::dedup_coordinates(line.clone().into_iter().collect());
-            pointts.push(Coordinate {
-                x: pointts[0].x,
-                y: pointts[0].y,
-            });
-            for i in 0..(pointts.len() - 1) {
-                let c = (pointts[i].x, pointts[i].y);
-                let d = (pointts[i + 1].x, pointts[i + 1].y);
-                if algebra::segment_intersects(a, b, c, d) > 0 {
-                    can_walk = false;
-                    break;
-                }
-            }
-            if can_walk == false {
-                break;
-            }
+// optional agent_radius, circle_segments and cutoff starting at args[first], clamps
+// circle_segments to mesh_geo::MAX_CIRCLE_SEGMENTS and cutoff to mesh_geo::MAX_LOCAL_CUTOFF
+fn decode_local_options<'a>(args: &[Term<'a>], first: usize) -> Result<mesh_geo::LocalGraphOptions, Error> {
+    let mut options = mesh_geo::LocalGraphOptions::default();
+    if args.len() > first {
+        let agent_radius = args[first].decode::<f64>()?;
+        if !agent_radius.is_finite() || agent_radius < 0.0 {
+            return Err(Error::BadArg);
         }
-        if can_walk == false {
-            break;
+        options.agent_radius = agent_radius;
+        options.circle_segments = args[first + 1].decode::<usize>()?.min(mesh_geo::MAX_CIRCLE_SEGMENTS);
+        let cutoff = args[first + 2].decode::<i64>()?;
+        if cutoff < 0 {
+            return Err(Error::BadArg);
         }
+        options.cutoff = (cutoff as u64).min(mesh_geo::MAX_LOCAL_CUTOFF);
     }
-
-    // check if line is in polys
-    // let line = LineString(vec![Coordinate{x: from.0 as f64, y: from.1 as f64}, Coordinate{x: to.0 as f64, y: to.1 as f64}]);
-    // let mut can_walk = false;
-    // for p in polys{
-    //     can_walk = p.contains(&line);
-    //     if can_walk {break;}
-    // }
-    return can_walk;
+    return Ok(options);
 }
 
 fn around_boxes<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
     let map_id: i64 = args[0].decode::<i64>()?;
     let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
-    let players: Vec<(i32, i32, f32)> = args[2].decode::<Vec<(i32, i32, f32)>>()?;
+    let obstacles = decode_obstacles(args[2])?;
+    let options = decode_local_options(args, 3)?;
 
-    let map: &mesh_geo::Map = map_by_map_id(map_id);
-    match mesh_geo::create_local_graph(map, from, &players) {
+    let map = map_by_map_id(map_id).read().unwrap();
+    match mesh_geo::create_local_graph(&map, from, &obstacles, &options) {
         Some(res) => {
             let (_, polys, _) = res;
             let mut re = vec![];
@@ -527,25 +685,26 @@ fn path_local<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
     let map_id: i64 = args[0].decode::<i64>()?;
     let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
     let to: (i64, i64) = args[2].decode::<(i64, i64)>()?;
-    let mut players: Vec<(i32, i32, f32)> = args[3].decode::<Vec<(i32, i32, f32)>>()?;
+    let mut obstacles = decode_obstacles(args[3])?;
+    let options = decode_local_options(args, 4)?;
     let path_time_start = Instant::now();
 
-    let map: &mesh_geo::Map = map_by_map_id(map_id);
-    let mut normal_path = match calc_path(map, from, to) {
+    let map = map_by_map_id(map_id).read().unwrap();
+    let mut normal_path = match calc_path(&map, from, to) {
         Some(res) => res,
         None => {
             return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
         }
     };
 
-    players.retain(|x| {
+    obstacles.retain(|x| {
         return mesh_geo::line_len((
             (from.0 as i32, from.1 as i32, 0.0 as f32),
-            (x.0, x.1, 0.0 as f32),
-        )) <= 1200;
+            (x.pos.0, x.pos.1, 0.0 as f32),
+        )) <= options.cutoff;
     });
 
-    if players.len() == 0 {
+    if obstacles.len() == 0 {
         return Ok((
             atoms::ok(),
             path_time_start.elapsed().as_micros() as u64,
@@ -577,7 +736,7 @@ fn path_local<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
     }
 
     let mut edges = Vec::new();
-    match mesh_geo::create_local_graph(map, from, &players) {
+    match local_graph::local_graph(map_id, &map, from, &obstacles, &options) {
         Some(res) => {
             let (graph, polys, nodes) = res;
             let from_idx = mesh_geo::find_closest_idx(&polys, from.0, from.1);
@@ -603,10 +762,17 @@ fn path_local<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
             let mut prevnode = p1[0];
             for node in p1 {
                 if prevnode != node {
+                    let center = polys[graph[prevnode]].center;
                     for edge in graph.edges_connecting(prevnode, node) {
                         let (_d, l) = edge.weight();
                         let (l0, l1) = l;
-                        edges.push(((l0.0, l0.1, l0.2), (l1.0, l1.1, l1.2)));
+                        edges.push(funnel::orient_portal(
+                            (center.0 as i64, center.1 as i64),
+                            (
+                                (l0.0 as i64, l0.1 as i64, l0.2),
+                                (l1.0 as i64, l1.1 as i64, l1.2),
+                            ),
+                        ));
                     }
                 }
                 prevnode = node;
@@ -617,21 +783,10 @@ fn path_local<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
         }
     }
 
-    let edgs: Vec<((i64, i64, f32), (i64, i64, f32))> = edges
-        .iter()
-        .map(|x| {
-            let (l1, l2) = x;
-            return (
-                (l1.0 as i64, l1.1 as i64, l1.2),
-                (l2.0 as i64, l2.1 as i64, l2.2),
-            );
-        })
-        .collect();
-
     let mut re = funnel::string_pull(
         (from.0, from.1, 0.0),
         (to_center.0, to_center.1, to_center.2),
-        edgs,
+        edges,
     );
 
     if re[0].0 == from.0 && re[0].1 == from.1 {
@@ -653,7 +808,7 @@ fn is_walkable<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
     let map_id: i64 = args[0].decode::<i64>()?;
     let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
 
-    let map: &mesh_geo::Map = map_by_map_id(map_id);
+    let map = map_by_map_id(map_id).read().unwrap();
     let g1 = mesh_geo::find_closest_node(&map.graph, from.0, from.1);    
 
     if mesh_geo::point_inside_poly(from.0 as i32, from.1 as i32, &map.graph[g1].1) == 0{
@@ -666,14 +821,69 @@ fn is_walkable<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
     );
 }
 
+// {{x, y, z}, size} is a circle of heat 1, {{x, y, z}, radius, weight, rings} falls off from weight
+// at the center over rings steps, at most mesh_geo::MAX_RINGS; sign is -1.0 for foes
+fn decode_heat_source<'a>(term: Term<'a>, sign: f64) -> Result<mesh_geo::HeatSource, Error> {
+    let t = rustler::types::tuple::get_tuple(term)?;
+    let source = match t.len() {
+        2 => mesh_geo::HeatSource {
+            pos: t[0].decode::<(i32, i32, f32)>()?,
+            radius: t[1].decode::<i32>()? as f64,
+            weight: sign,
+            rings: 1,
+        },
+        4 => mesh_geo::HeatSource {
+            pos: t[0].decode::<(i32, i32, f32)>()?,
+            radius: t[1].decode::<f64>()?,
+            weight: sign * t[2].decode::<f64>()?,
+            rings: t[3].decode::<usize>()?.min(mesh_geo::MAX_RINGS),
+        },
+        _ => return Err(Error::BadArg),
+    };
+    if !source.radius.is_finite() || source.radius <= 0.0 || !source.weight.is_finite() {
+        return Err(Error::BadArg);
+    }
+    return Ok(source);
+}
+
+fn decode_heat_sources<'a>(term: Term<'a>, sign: f64) -> Result<Vec<mesh_geo::HeatSource>, Error> {
+    let mut sources = vec![];
+    for item in term.decode::<Vec<Term<'a>>>()? {
+        sources.push(decode_heat_source(item, sign)?);
+    }
+    return Ok(sources);
+}
+
+// {:linear, factor}, {:exponential, base, cap} or {:threshold, factor, threshold}
+fn decode_heat_cost<'a>(term: Term<'a>) -> Result<mesh_geo::HeatCost, Error> {
+    let t = rustler::types::tuple::get_tuple(term)?;
+    if t.len() < 2 {
+        return Err(Error::BadArg);
+    }
+    let kind = t[0].decode::<rustler::types::atom::Atom>()?;
+    if kind == atoms::linear() {
+        return Ok(mesh_geo::HeatCost::Linear(t[1].decode::<f64>()?));
+    }
+    if t.len() < 3 {
+        return Err(Error::BadArg);
+    }
+    if kind == atoms::exponential() {
+        return Ok(mesh_geo::HeatCost::Exponential(t[1].decode::<f64>()?, t[2].decode::<f64>()?));
+    }
+    if kind == atoms::threshold() {
+        return Ok(mesh_geo::HeatCost::Threshold(t[1].decode::<f64>()?, t[2].decode::<f64>()?));
+    }
+    return Err(Error::BadArg);
+}
+
 fn heat_maps<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
     let map_id: i64 = args[0].decode::<i64>()?;
     let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
-    let players: Vec<((i32, i32, f32), i32)> = args[2].decode::<Vec<((i32, i32, f32), i32)>>()?;
-    let foes: Vec<((i32, i32, f32), i32)> = args[3].decode::<Vec<((i32, i32, f32), i32)>>()?;    
+    let mut sources = decode_heat_sources(args[2], 1.0)?;
+    sources.extend(decode_heat_sources(args[3], -1.0)?);
 
-    let map: &mesh_geo::Map = map_by_map_id(map_id);
-    match mesh_geo::create_heatmap_graph(map, from, &players, &foes) {
+    let map = map_by_map_id(map_id).read().unwrap();
+    match mesh_geo::create_heatmap_graph(&map, from, &sources, &mesh_geo::HeatCost::default()) {
         Some(res) => {
             let (_, polys, _) = res;
             let mut re = vec![];
@@ -690,46 +900,37 @@ fn heat_maps<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
     return Ok((atoms::error(), 1 as u64).encode(env));
 }
 
-fn path_heatmap<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
-    let map_id: i64 = args[0].decode::<i64>()?;
-    let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
-    let to: (i64, i64) = args[2].decode::<(i64, i64)>()?;
-    let mut players: Vec<((i32, i32, f32), i32)> = args[3].decode::<Vec<((i32, i32, f32), i32)>>()?;
-    let mut foes: Vec<((i32, i32, f32), i32)> = args[4].decode::<Vec<((i32, i32, f32), i32)>>()?;
-    let path_time_start = Instant::now();
-
-    let map: &mesh_geo::Map = map_by_map_id(map_id);
-    let mut normal_path = match calc_path(map, from, to) {
-        Some(res) => res,
-        None => {
-            return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
-        }
-    };
-
-    players.retain(|x| {
-        return mesh_geo::line_len((
-            (from.0 as i32, from.1 as i32, 0.0 as f32),
-            ((x.0).0, (x.0).1, 0.0 as f32),
-        )) <= 2000;
-    });
+fn calc_heatmap_path(
+    map: &mesh_geo::Map,
+    from: (i64, i64),
+    to: (i64, i64),
+    sources: &mut Vec<mesh_geo::HeatSource>,
+    cost: &mesh_geo::HeatCost,
+) -> Option<Vec<(i64, i64, f32)>> {
+    let mut normal_path = calc_path(map, from, to)?;
 
-    foes.retain(|x| {
+    sources.retain(|x| {
         return mesh_geo::line_len((
             (from.0 as i32, from.1 as i32, 0.0 as f32),
-            ((x.0).0, (x.0).1, 0.0 as f32),
+            (x.pos.0, x.pos.1, 0.0 as f32),
         )) <= 2000;
     });
 
-
-    if players.len() == 0 && foes.len() ==0{
-        return Ok((
-            atoms::ok(),
-            path_time_start.elapsed().as_micros() as u64,
-            normal_path,
-        )
-            .encode(env));
+    if sources.len() == 0 {
+        return Some(normal_path);
     }
 
+    let graph = mesh_geo::create_heatmap_graph(map, from, sources, cost);
+    return heat_graph_path(from, to, normal_path, graph);
+}
+
+// local part of the path through the heat graph, then the rest of the normal path
+fn heat_graph_path(
+    from: (i64, i64),
+    to: (i64, i64),
+    mut normal_path: Vec<(i64, i64, f32)>,
+    graph: Option<(Graph<usize, (f64, mesh_geo::Line)>, Vec<mesh_geo::PrePolyWithHeat>, Vec<NodeIndex>)>,
+) -> Option<Vec<(i64, i64, f32)>> {
     // find to position
     let mut to_center = (to.0, to.1, 0. as f32);
     let mut to_center_idx = -1;
@@ -753,7 +954,7 @@ fn path_heatmap<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error>
     }
 
     let mut edges = Vec::new();
-    match mesh_geo::create_heatmap_graph(map, from, &players, &foes) {
+    match graph {
         Some(res) => {
             let (graph, polys, nodes) = res;
             let from_idx = mesh_geo::find_closest_idx_0(&polys, from.0, from.1);
@@ -772,42 +973,36 @@ fn path_heatmap<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error>
 
             let (_weight, p1) = path.or(Some((0.0, [].to_vec()))).unwrap();
             if p1.len() == 0 {
-                return Ok(
-                    (atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env),
-                );
+                return None;
             }
             let mut prevnode = p1[0];
             for node in p1 {
                 if prevnode != node {
+                    let center = (polys[graph[prevnode]].0).center;
                     for edge in graph.edges_connecting(prevnode, node) {
                         let (_d, l) = edge.weight();
                         let (l0, l1) = l;
-                        edges.push(((l0.0, l0.1, l0.2), (l1.0, l1.1, l1.2)));
+                        edges.push(funnel::orient_portal(
+                            (center.0 as i64, center.1 as i64),
+                            (
+                                (l0.0 as i64, l0.1 as i64, l0.2),
+                                (l1.0 as i64, l1.1 as i64, l1.2),
+                            ),
+                        ));
                     }
                 }
                 prevnode = node;
             }
         }
         None => {
-            return Ok((atoms::error(), 0 as u64).encode(env));
+            return None;
         }
     }
 
-    let edgs: Vec<((i64, i64, f32), (i64, i64, f32))> = edges
-        .iter()
-        .map(|x| {
-            let (l1, l2) = x;
-            return (
-                (l1.0 as i64, l1.1 as i64, l1.2),
-                (l2.0 as i64, l2.1 as i64, l2.2),
-            );
-        })
-        .collect();
-
     let mut re = funnel::string_pull(
         (from.0, from.1, 0.0),
         (to_center.0, to_center.1, to_center.2),
-        edgs,
+        edges,
     );
 
     if re[0].0 == from.0 && re[0].1 == from.1 {
@@ -817,6 +1012,469 @@ fn path_heatmap<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error>
     for n in normal_path {
         re.push(n);
     }
+    return Some(re);
+}
+
+fn path_heatmap<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    let to: (i64, i64) = args[2].decode::<(i64, i64)>()?;
+    let mut sources = decode_heat_sources(args[3], 1.0)?;
+    sources.extend(decode_heat_sources(args[4], -1.0)?);
+    let mut cost = mesh_geo::HeatCost::default();
+    if args.len() > 5 {
+        cost = decode_heat_cost(args[5])?;
+    }
+    let path_time_start = Instant::now();
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    match calc_heatmap_path(&map, from, to, &mut sources, &cost) {
+        Some(re) => {
+            return Ok((
+                atoms::ok(),
+                path_time_start.elapsed().as_micros() as u64,
+                re,
+            )
+                .encode(env));
+        }
+        None => {
+            return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
+        }
+    }
+}
+
+fn add_obstacle<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let id: u64 = args[1].decode::<u64>()?;
+    let shape: Vec<(i64, i64)> = args[2].decode::<Vec<(i64, i64)>>()?;
+    let path_time_start = Instant::now();
+
+    if shape.len() < 3 {
+        return Ok((atoms::error(), 0 as u64).encode(env));
+    }
+    let mut points: Vec<Coordinate<f64>> = shape
+        .iter()
+        .map(|(x, y)| Coordinate {
+            x: *x as f64,
+            y: *y as f64,
+        })
+        .collect();
+    points.push(points[0]);
+
+    let mut map = match known_map(map_id) {
+        Some(map) => map.write().unwrap(),
+        None => return Ok((atoms::error(), 0 as u64).encode(env)),
+    };
+    mesh_geo::add_obstacle(&mut map, id, Polygon::new(LineString(points), vec![]));
+    return Ok((atoms::ok(), path_time_start.elapsed().as_micros() as u64).encode(env));
+}
+
+fn remove_obstacle<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let id: u64 = args[1].decode::<u64>()?;
+    let path_time_start = Instant::now();
+
+    let mut map = match known_map(map_id) {
+        Some(map) => map.write().unwrap(),
+        None => return Ok((atoms::error(), 0 as u64).encode(env)),
+    };
+    if mesh_geo::remove_obstacle(&mut map, id) {
+        return Ok((atoms::ok(), path_time_start.elapsed().as_micros() as u64).encode(env));
+    }
+    return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
+}
+
+fn flow_field<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let target: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    // walking distance between polygon centers, like reachable_within
+    let radius: u64 = args[2].decode::<u64>()?;
+    let path_time_start = Instant::now();
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    let field = ResourceArc::new(flow_field::build(map_id, &map, target, radius));
+    return Ok((
+        atoms::ok(),
+        path_time_start.elapsed().as_micros() as u64,
+        field,
+    )
+        .encode(env));
+}
+
+fn flow_direction<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let field: ResourceArc<flow_field::FlowField> =
+        args[0].decode::<ResourceArc<flow_field::FlowField>>()?;
+    let pos: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+
+    let map = map_by_map_id(field.map_id).read().unwrap();
+    match field.direction(&map, pos.0, pos.1) {
+        Some(dir) => return Ok((atoms::ok(), dir).encode(env)),
+        None => return Ok((atoms::error(), 0 as u64).encode(env)),
+    }
+}
+
+fn reachable_within<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    // walking distance between polygon centers, not the weighted path cost
+    let max_dist: u64 = args[2].decode::<u64>()?;
+    let mut with_boundary = false;
+    if args.len() > 3 {
+        with_boundary = args[3].decode::<bool>()?;
+    }
+    let path_time_start = Instant::now();
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    let reached = mesh_geo::reachable_polys(&map, from, max_dist);
+    let ids: Vec<(u64, u64)> = reached
+        .iter()
+        .map(|(node, cost)| ((map.graph[*node]).0, *cost))
+        .collect();
+
+    if !with_boundary {
+        return Ok((
+            atoms::ok(),
+            path_time_start.elapsed().as_micros() as u64,
+            ids,
+        )
+            .encode(env));
+    }
+
+    let polys: Vec<Polygon<f64>> = reached
+        .iter()
+        .map(|(node, _)| mesh_geo::prepoly_to_polygon(&(map.graph[*node]).1))
+        .collect();
+    let mut boundary = vec![];
+    for p in mesh_geo::merge_polygons(polys) {
+        let exterior: Vec<(i64, i64)> = p.exterior().0.iter().map(|c| (c.x as i64, c.y as i64)).collect();
+        let interiors: Vec<Vec<(i64, i64)>> = p
+            .interiors()
+            .iter()
+            .map(|line| line.0.iter().map(|c| (c.x as i64, c.y as i64)).collect())
+            .collect();
+        boundary.push((exterior, interiors));
+    }
+    return Ok((
+        atoms::ok(),
+        path_time_start.elapsed().as_micros() as u64,
+        ids,
+        boundary,
+    )
+        .encode(env));
+}
+
+fn random_point<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let center: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    // walking distance between polygon centers, like reachable_within
+    let radius: u64 = args[2].decode::<u64>()?;
+    let mut rng = sampling::Rng::from_time();
+    if args.len() > 3 {
+        rng = sampling::Rng::new(args[3].decode::<u64>()?);
+    }
+    let path_time_start = Instant::now();
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    match sampling::random_point(&map, center, radius, &mut rng) {
+        Some(point) => {
+            return Ok((
+                atoms::ok(),
+                path_time_start.elapsed().as_micros() as u64,
+                point,
+            )
+                .encode(env));
+        }
+        None => (),
+    }
+    return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
+}
+
+fn path_distance<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    let to: (i64, i64) = args[2].decode::<(i64, i64)>()?;
+    let path_time_start = Instant::now();
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    match calc_corridor(&map, from, to) {
+        Some(nodes) => {
+            let dist = corridor_distance(&map, from, to, &nodes);
+            return Ok((
+                atoms::ok(),
+                path_time_start.elapsed().as_micros() as u64,
+                dist,
+            )
+                .encode(env));
+        }
+        None => return Ok(atoms::unreachable().encode(env)),
+    }
+}
+
+fn path_distance_many<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    let targets: Vec<(i64, i64)> = args[2].decode::<Vec<(i64, i64)>>()?;
+    // weighted path cost past which targets are unreachable, by default 4 times the
+    // farthest straight line at the narrow portal weight of 3.2, plus a tile
+    let mut max_cost: u64 = 1260;
+    for t in &targets {
+        max_cost = max_cost.max(heur_dist(&from, t) * 13 + 1260);
+    }
+    if args.len() > 3 {
+        max_cost = args[3].decode::<u64>()?;
+    }
+    let path_time_start = Instant::now();
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    let g1 = mesh_geo::find_closest_node(&map.graph, from.0, from.1);
+    let target_nodes: Vec<NodeIndex> = targets
+        .iter()
+        .map(|t| mesh_geo::find_closest_node(&map.graph, t.0, t.1))
+        .collect();
+
+    // one search for all targets, stop once the last one is settled
+    let mut remaining = target_nodes.clone();
+    let visited = mesh_geo::dijkstra_bounded(&map.graph, &vec![g1], max_cost, |node| {
+        remaining.retain(|t| *t != node);
+        return remaining.len() == 0;
+    });
+
+    let mut re: Vec<Term<'a>> = vec![];
+    for i in 0..targets.len() {
+        if !visited.contains_key(&target_nodes[i]) {
+            re.push(atoms::unreachable().encode(env));
+            continue;
+        }
+        let nodes = mesh_geo::dijkstra_chain(&visited, target_nodes[i]);
+        re.push(corridor_distance(&map, from, targets[i], &nodes).encode(env));
+    }
+    return Ok((
+        atoms::ok(),
+        path_time_start.elapsed().as_micros() as u64,
+        re,
+    )
+        .encode(env));
+}
+
+fn nearest_by_path<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    let candidates: Vec<(i64, i64)> = args[2].decode::<Vec<(i64, i64)>>()?;
+    let max_cost: u64 = args[3].decode::<u64>()?;
+    let mut with_path = false;
+    if args.len() > 4 {
+        with_path = args[4].decode::<bool>()?;
+    }
+    let path_time_start = Instant::now();
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    let g1 = mesh_geo::find_closest_node(&map.graph, from.0, from.1);
+    let candidate_nodes: Vec<NodeIndex> = candidates
+        .iter()
+        .map(|c| mesh_geo::find_closest_node(&map.graph, c.0, c.1))
+        .collect();
+
+    let mut found = None;
+    let visited = mesh_geo::dijkstra_bounded(&map.graph, &vec![g1], max_cost, |node| {
+        found = candidate_nodes.iter().position(|c| *c == node);
+        return found.is_some();
+    });
+
+    let idx = match found {
+        Some(idx) => idx,
+        None => return Ok(atoms::unreachable().encode(env)),
+    };
+    let nodes = mesh_geo::dijkstra_chain(&visited, candidate_nodes[idx]);
+    let dist = corridor_distance(&map, from, candidates[idx], &nodes);
+
+    if !with_path {
+        return Ok((
+            atoms::ok(),
+            path_time_start.elapsed().as_micros() as u64,
+            idx as u64,
+            dist,
+        )
+            .encode(env));
+    }
+    let re = corridor_path(&map, from, candidates[idx], &nodes);
+    return Ok((
+        atoms::ok(),
+        path_time_start.elapsed().as_micros() as u64,
+        idx as u64,
+        dist,
+        re,
+    )
+        .encode(env));
+}
+
+// first point along the path within range of target that can see it, path cut there
+fn truncate_to_range(
+    map: &mesh_geo::Map,
+    from: (i64, i64),
+    target: (i64, i64),
+    path: Vec<(i64, i64, f32)>,
+    range: u64,
+) -> Vec<(i64, i64, f32)> {
+    let step = 100.0;
+    let r = range as f64;
+    let (tx, ty) = (target.0 as f64, target.1 as f64);
+    let mut re = vec![];
+    let mut prev = (from.0 as f64, from.1 as f64, path[0].2);
+
+    for p in path {
+        let (dx, dy) = (p.0 as f64 - prev.0, p.1 as f64 - prev.1);
+        let seg_len = (dx * dx + dy * dy).sqrt();
+
+        // where the segment enters and leaves the range circle
+        let (fx, fy) = (prev.0 - tx, prev.1 - ty);
+        let a = dx * dx + dy * dy;
+        let b = 2.0 * (fx * dx + fy * dy);
+        let c = fx * fx + fy * fy - r * r;
+        let disc = b * b - 4.0 * a * c;
+        if a > 0.0 && disc >= 0.0 {
+            // kept 2 units inside the circle, integer positions can land just outside it
+            let margin = 2.0 / seg_len;
+            let mut t = ((-b - disc.sqrt()) / (2.0 * a) + margin).max(0.0);
+            let t_exit = ((-b + disc.sqrt()) / (2.0 * a) - margin).min(1.0);
+            while t <= t_exit {
+                let pos = ((prev.0 + dx * t) as i64, (prev.1 + dy * t) as i64);
+                if can_walk_direct(map, pos, target) {
+                    re.push((pos.0, pos.1, prev.2 + (p.2 - prev.2) * t as f32));
+                    return re;
+                }
+                t += step / seg_len;
+            }
+        }
+        re.push(p);
+        prev = (p.0 as f64, p.1 as f64, p.2);
+    }
+    return re;
+}
+
+fn path_in_range<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    let to: (i64, i64) = args[2].decode::<(i64, i64)>()?;
+    let range: u64 = args[3].decode::<u64>()?;
+    let path_time_start = Instant::now();
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    if heur_dist(&from, &to) <= range && can_walk_direct(&map, from, to) {
+        return Ok(atoms::in_range().encode(env));
+    }
+
+    let nodes = match calc_corridor(&map, from, to) {
+        Some(nodes) => nodes,
+        None => {
+            return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
+        }
+    };
+    let normal_path = corridor_path(&map, from, to, &nodes);
+    let re = truncate_to_range(&map, from, to, normal_path, range);
+    return Ok((
+        atoms::ok(),
+        path_time_start.elapsed().as_micros() as u64,
+        re,
+    )
+        .encode(env));
+}
+
+fn flee_path<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    let threats: Vec<(i64, i64)> = args[2].decode::<Vec<(i64, i64)>>()?;
+    let distance: u64 = args[3].decode::<u64>()?;
+    let mut threat_radius = 0;
+    if args.len() > 4 {
+        threat_radius = args[4].decode::<i32>()?;
+    }
+    let path_time_start = Instant::now();
+
+    // nothing to flee from, every polygon would score the same
+    if threats.len() == 0 {
+        return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
+    }
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    let nodes = mesh_geo::flee_corridor(&map, from, &threats, distance);
+    if nodes.len() == 1 {
+        return Ok(atoms::same_polygon().encode(env));
+    }
+    let center = (map.graph[nodes[nodes.len() - 1]]).1.center;
+    let to = (center.0 as i64, center.1 as i64);
+
+    // with a threat radius the way out avoids the threats like path_heatmap does
+    let re = if threat_radius > 0 {
+        let mut foes: Vec<mesh_geo::HeatSource> = threats
+            .iter()
+            .map(|t| mesh_geo::HeatSource {
+                pos: (t.0 as i32, t.1 as i32, 0.0),
+                radius: threat_radius as f64,
+                weight: -1.0,
+                rings: 1,
+            })
+            .collect();
+        calc_heatmap_path(&map, from, to, &mut foes, &mesh_geo::HeatCost::default())
+    } else {
+        Some(corridor_path(&map, from, to, &nodes))
+    };
+
+    match re {
+        Some(re) => {
+            return Ok((
+                atoms::ok(),
+                path_time_start.elapsed().as_micros() as u64,
+                re,
+            )
+                .encode(env));
+        }
+        None => {
+            return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
+        }
+    }
+}
+
+fn resample_path<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let points: Vec<(i64, i64, f32)> = args[1].decode::<Vec<(i64, i64, f32)>>()?;
+    let step: f64 = args[2].decode::<f64>()?;
+    let path_time_start = Instant::now();
+
+    if step <= 0.0 {
+        return Ok((atoms::error(), 0 as u64).encode(env));
+    }
+    let map = map_by_map_id(map_id).read().unwrap();
+    let points: Vec<(f64, f64, f32)> = points.iter().map(|p| (p.0 as f64, p.1 as f64, p.2)).collect();
+    let re = smoothing::resample(&map, &points, step);
+    return Ok((
+        atoms::ok(),
+        path_time_start.elapsed().as_micros() as u64,
+        re,
+    )
+        .encode(env));
+}
+
+fn smooth_path<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let points: Vec<(i64, i64, f32)> = args[1].decode::<Vec<(i64, i64, f32)>>()?;
+    let mode: rustler::types::atom::Atom = args[2].decode::<rustler::types::atom::Atom>()?;
+    let step: f64 = args[3].decode::<f64>()?;
+    let path_time_start = Instant::now();
+
+    if step <= 0.0 {
+        return Ok((atoms::error(), 0 as u64).encode(env));
+    }
+    let map = map_by_map_id(map_id).read().unwrap();
+    let points: Vec<(f64, f64, f32)> = points.iter().map(|p| (p.0 as f64, p.1 as f64, p.2)).collect();
+    // bezier rounds each corner over two steps on either side
+    let curve = if mode == atoms::catmull_rom() {
+        smoothing::catmull_rom(&map, &points, step)
+    } else if mode == atoms::bezier() {
+        smoothing::bezier(&map, &points, step * 2.0, step)
+    } else {
+        return Ok((atoms::error(), 0 as u64).encode(env));
+    };
+    let re = smoothing::resample(&map, &curve, step);
     return Ok((
         atoms::ok(),
         path_time_start.elapsed().as_micros() as u64,
@@ -825,3 +1483,314 @@ fn path_heatmap<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error>
         .encode(env));
 }
 
+fn moving_path_new<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let points: Vec<(i64, i64, f32)> = args[0].decode::<Vec<(i64, i64, f32)>>()?;
+    let speed: f64 = args[1].decode::<f64>()?;
+    let start_time: f64 = args[2].decode::<f64>()?;
+
+    let points: Vec<(f64, f64, f32)> = points.iter().map(|p| (p.0 as f64, p.1 as f64, p.2)).collect();
+    match moving_path::MovingPath::new(points, speed, start_time) {
+        Some(path) => return Ok((atoms::ok(), ResourceArc::new(path)).encode(env)),
+        None => return Ok((atoms::error(), 0 as u64).encode(env)),
+    }
+}
+
+fn moving_path_position_at<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let path: ResourceArc<moving_path::MovingPath> =
+        args[0].decode::<ResourceArc<moving_path::MovingPath>>()?;
+    let t: f64 = args[1].decode::<f64>()?;
+
+    let (x, y, z) = path.position_at(t);
+    return Ok((atoms::ok(), (x as i64, y as i64, z)).encode(env));
+}
+
+fn moving_path_remaining_distance<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let path: ResourceArc<moving_path::MovingPath> =
+        args[0].decode::<ResourceArc<moving_path::MovingPath>>()?;
+    let t: f64 = args[1].decode::<f64>()?;
+
+    return Ok((atoms::ok(), path.remaining_distance(t)).encode(env));
+}
+
+fn moving_path_progress_to<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let path: ResourceArc<moving_path::MovingPath> =
+        args[0].decode::<ResourceArc<moving_path::MovingPath>>()?;
+    let point: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+
+    let (along, fraction) = path.progress_to(point.0 as f64, point.1 as f64);
+    return Ok((atoms::ok(), along, fraction).encode(env));
+}
+
+fn moving_path_set_speed<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let path: ResourceArc<moving_path::MovingPath> =
+        args[0].decode::<ResourceArc<moving_path::MovingPath>>()?;
+    let t: f64 = args[1].decode::<f64>()?;
+    let speed: f64 = args[2].decode::<f64>()?;
+
+    path.set_speed(t, speed);
+    return Ok(atoms::ok().encode(env));
+}
+
+fn crowd_new<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let mut neighbor_dist: f64 = 600.0;
+    let mut time_horizon: f64 = 2.0;
+    if args.len() > 1 {
+        neighbor_dist = args[1].decode::<f64>()?;
+        time_horizon = args[2].decode::<f64>()?;
+    }
+
+    match crowd::Crowd::new(map_id, neighbor_dist, time_horizon) {
+        Some(crowd) => return Ok((atoms::ok(), ResourceArc::new(crowd)).encode(env)),
+        None => return Err(Error::BadArg),
+    }
+}
+
+fn crowd_add_agent<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let crowd: ResourceArc<crowd::Crowd> = args[0].decode::<ResourceArc<crowd::Crowd>>()?;
+    let id: u64 = args[1].decode::<u64>()?;
+    let pos: (i64, i64) = args[2].decode::<(i64, i64)>()?;
+    let radius: f64 = args[3].decode::<f64>()?;
+    let max_speed: f64 = args[4].decode::<f64>()?;
+
+    let map = map_by_map_id(crowd.map_id).read().unwrap();
+    if crowd.add_agent(&map, id, (pos.0 as f64, pos.1 as f64), radius, max_speed) {
+        return Ok(atoms::ok().encode(env));
+    }
+    return Ok(atoms::error().encode(env));
+}
+
+fn crowd_remove_agent<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let crowd: ResourceArc<crowd::Crowd> = args[0].decode::<ResourceArc<crowd::Crowd>>()?;
+    let id: u64 = args[1].decode::<u64>()?;
+
+    if crowd.remove_agent(id) {
+        return Ok(atoms::ok().encode(env));
+    }
+    return Ok(atoms::error().encode(env));
+}
+
+fn crowd_set_velocity<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let crowd: ResourceArc<crowd::Crowd> = args[0].decode::<ResourceArc<crowd::Crowd>>()?;
+    let id: u64 = args[1].decode::<u64>()?;
+    let velocity: (f64, f64) = args[2].decode::<(f64, f64)>()?;
+
+    if crowd.set_preferred_velocity(id, velocity) {
+        return Ok(atoms::ok().encode(env));
+    }
+    return Ok(atoms::error().encode(env));
+}
+
+fn crowd_step<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let path_time_start = Instant::now();
+
+    let crowd: ResourceArc<crowd::Crowd> = args[0].decode::<ResourceArc<crowd::Crowd>>()?;
+    let dt: f64 = args[1].decode::<f64>()?;
+    if dt <= 0.0 {
+        return Ok((atoms::error(), 0 as u64).encode(env));
+    }
+
+    let map = map_by_map_id(crowd.map_id).read().unwrap();
+    let agents: Vec<(u64, (i64, i64, f32), (f64, f64))> = crowd
+        .step(&map, dt)
+        .iter()
+        .map(|(id, (x, y, z), velocity)| (*id, (*x as i64, *y as i64, *z), *velocity))
+        .collect();
+    return Ok((atoms::ok(), path_time_start.elapsed().as_micros() as u64, agents).encode(env));
+}
+
+// the source keeps its sign as given: {{x, y, z}, size} is always a friendly heat of 1,
+// foes on a danger layer need {{x, y, z}, radius, weight, rings} with a negative weight
+fn influence_set<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let layer: String = args[1].atom_to_string()?;
+    let id: u64 = args[2].decode::<u64>()?;
+    let source = decode_heat_source(args[3], 1.0)?;
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    influence::set_source(map_id, &map, &layer, id, source);
+    return Ok(atoms::ok().encode(env));
+}
+
+fn influence_remove<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let layer: String = args[1].atom_to_string()?;
+    let id: u64 = args[2].decode::<u64>()?;
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    if influence::remove_source(map_id, &map, &layer, id) {
+        return Ok(atoms::ok().encode(env));
+    }
+    return Ok(atoms::error().encode(env));
+}
+
+fn influence_clear<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let layer: String = args[1].atom_to_string()?;
+
+    if influence::clear_layer(map_id, &layer) {
+        return Ok(atoms::ok().encode(env));
+    }
+    return Ok(atoms::error().encode(env));
+}
+
+fn path_influence<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    let to: (i64, i64) = args[2].decode::<(i64, i64)>()?;
+    let layer: String = args[3].atom_to_string()?;
+    let mut cost = mesh_geo::HeatCost::default();
+    if args.len() > 4 {
+        cost = decode_heat_cost(args[4])?;
+    }
+    let path_time_start = Instant::now();
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    let re = match calc_path(&map, from, to) {
+        Some(normal_path) => {
+            let graph = influence::layer_heatmap_graph(map_id, &map, &layer, from, &cost);
+            heat_graph_path(from, to, normal_path, graph)
+        }
+        None => None,
+    };
+    match re {
+        Some(re) => {
+            return Ok((
+                atoms::ok(),
+                path_time_start.elapsed().as_micros() as u64,
+                re,
+            )
+                .encode(env));
+        }
+        None => {
+            return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
+        }
+    }
+}
+
+fn influence_half_life<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let layer: String = args[1].atom_to_string()?;
+    let half_life: f64 = args[2].decode::<f64>()?;
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    influence::set_half_life(map_id, &map, &layer, half_life);
+    return Ok(atoms::ok().encode(env));
+}
+
+fn influence_step<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let dt: f64 = args[1].decode::<f64>()?;
+    if !dt.is_finite() || dt < 0.0 {
+        return Err(Error::BadArg);
+    }
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    influence::step(map_id, &map, dt);
+    return Ok(atoms::ok().encode(env));
+}
+
+fn influence_at<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let layer: String = args[1].atom_to_string()?;
+    let pos: (i64, i64) = args[2].decode::<(i64, i64)>()?;
+
+    match influence::heat_at(map_id, &layer, pos.0 as f64, pos.1 as f64) {
+        Some(heat) => return Ok((atoms::ok(), heat).encode(env)),
+        None => return Ok(atoms::error().encode(env)),
+    }
+}
+
+fn influence_along<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let layer: String = args[1].atom_to_string()?;
+    let points: Vec<(i64, i64, f32)> = args[2].decode::<Vec<(i64, i64, f32)>>()?;
+
+    let points: Vec<(f64, f64)> = points.iter().map(|p| (p.0 as f64, p.1 as f64)).collect();
+    match influence::heat_along(map_id, &layer, &points) {
+        Some((total, peak)) => return Ok((atoms::ok(), total, peak).encode(env)),
+        None => return Ok(atoms::error().encode(env)),
+    }
+}
+
+// cells x cells f32 values, little endian and row by row from the lowest x and y, NaN off the navmesh
+fn influence_raster<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let layer: String = args[1].atom_to_string()?;
+    let center: (i64, i64) = args[2].decode::<(i64, i64)>()?;
+    // clamped to mesh_geo::MAX_RASTER_CELLS, the returned cells is the side actually used
+    let cells: usize = args[3].decode::<usize>()?.min(mesh_geo::MAX_RASTER_CELLS);
+    let cell_size: f64 = args[4].decode::<f64>()?;
+    let path_time_start = Instant::now();
+
+    if !(cell_size > 0.0) {
+        return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
+    }
+    let size = match cells.checked_mul(cells).and_then(|n| n.checked_mul(4)) {
+        Some(size) => size,
+        None => return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env)),
+    };
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    let values = match influence::raster(map_id, &map, &layer, (center.0 as f64, center.1 as f64), cells, cell_size) {
+        Some(values) => values,
+        None => return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env)),
+    };
+    let mut binary = match OwnedBinary::new(size) {
+        Some(binary) => binary,
+        None => return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env)),
+    };
+    for (i, v) in values.iter().enumerate() {
+        binary.as_mut_slice()[i * 4..i * 4 + 4].copy_from_slice(&v.to_le_bytes());
+    }
+    let took = path_time_start.elapsed().as_micros() as u64;
+    return Ok((atoms::ok(), took, cells, binary.release(env)).encode(env));
+}
+
+// keyword list, e.g. [heat: {:danger, 1.0}, threats: [{x, y}], threat_distance: 1.0,
+// line_of_sight: -1.0, wall: 0.5, travel: 0.2, samples: 64, top: 5, seed: 1],
+// samples are capped at tactical::MAX_SAMPLES and radius is walking distance as in reachable_within
+fn find_tactical_position<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
+    let map_id: i64 = args[0].decode::<i64>()?;
+    let from: (i64, i64) = args[1].decode::<(i64, i64)>()?;
+    let radius: u64 = args[2].decode::<u64>()?;
+    let mut criteria = tactical::Criteria::default();
+    let mut rng = sampling::Rng::from_time();
+    for item in args[3].decode::<Vec<Term<'a>>>()? {
+        let (key, value): (rustler::types::atom::Atom, Term<'a>) = item.decode::<(rustler::types::atom::Atom, Term<'a>)>()?;
+        if key == atoms::heat() {
+            let (layer, weight): (Term<'a>, f64) = value.decode::<(Term<'a>, f64)>()?;
+            criteria.heat = Some((layer.atom_to_string()?, weight));
+        } else if key == atoms::threats() {
+            let threats: Vec<(i64, i64)> = value.decode::<Vec<(i64, i64)>>()?;
+            criteria.threats = threats.iter().map(|t| (t.0 as f64, t.1 as f64)).collect();
+        } else if key == atoms::threat_distance() {
+            criteria.threat_distance = value.decode::<f64>()?;
+        } else if key == atoms::line_of_sight() {
+            criteria.line_of_sight = value.decode::<f64>()?;
+        } else if key == atoms::wall() {
+            criteria.wall = value.decode::<f64>()?;
+        } else if key == atoms::travel() {
+            criteria.travel = value.decode::<f64>()?;
+        } else if key == atoms::samples() {
+            criteria.samples = value.decode::<usize>()?;
+        } else if key == atoms::top() {
+            criteria.top = value.decode::<usize>()?;
+        } else if key == atoms::seed() {
+            rng = sampling::Rng::new(value.decode::<u64>()?);
+        } else {
+            return Err(Error::BadArg);
+        }
+    }
+    let path_time_start = Instant::now();
+
+    let map = map_by_map_id(map_id).read().unwrap();
+    let found: Vec<(i64, i64, f32, f64)> = tactical::find_positions(map_id, &map, from, radius, &criteria, &mut rng)
+        .iter()
+        .map(|(p, score)| (p.0, p.1, p.2, *score))
+        .collect();
+    if found.len() == 0 {
+        return Ok((atoms::error(), path_time_start.elapsed().as_micros() as u64).encode(env));
+    }
+    return Ok((atoms::ok(), path_time_start.elapsed().as_micros() as u64, found).encode(env));
+}
@@ -0,0 +1,219 @@
+use crate::mesh_geo;
+use geo_clipper::Clipper;
+use geo_types::Polygon;
+use lazy_static::lazy_static;
+use petgraph::graph::NodeIndex;
+use petgraph::Graph;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// windows kept before the cache is dropped
+const MAX_WINDOWS: usize = 256;
+
+type ObstacleKey = Vec<Vec<(i64, i64)>>;
+
+type LocalLink = (usize, usize, (u64, mesh_geo::Line));
+
+// triangles of one map polygon, clipped by the obstacles it was built with
+struct Piece {
+    obstacles: Option<ObstacleKey>,
+    tris: Vec<mesh_geo::PrePoly>,
+}
+
+// the local graph of a tile window, kept between calls
+struct Window {
+    revision: u64,
+    base: Vec<Polygon<f64>>,
+    bounds: Vec<mesh_geo::Bounds>,
+    // polygons after this one whose bounds touch it
+    neighbours: Vec<Vec<usize>>,
+    pos_to_z: HashMap<(i32, i32), f32>,
+    pieces: Vec<Piece>,
+    // links between the triangles of two pieces, keyed by piece indexes
+    links: HashMap<(usize, usize), Vec<LocalLink>>,
+}
+
+lazy_static! {
+    static ref WINDOWS: Mutex<HashMap<(i64, Vec<usize>), Window>> = Mutex::new(HashMap::new());
+}
+
+fn new_window(map: &mesh_geo::Map, tiles: &Vec<usize>) -> Window {
+    let mut base = vec![];
+    let mut pos_to_z = HashMap::<(i32, i32), f32>::new();
+    for i_t in tiles {
+        for p in &map.polygons[*i_t] {
+            base.push(p.clone());
+        }
+        for area in &map.tiles[*i_t].areas {
+            for v in &area.verts {
+                pos_to_z.insert((v.0, v.1), v.2);
+            }
+        }
+    }
+    let bounds: Vec<mesh_geo::Bounds> = base.iter().map(|p| mesh_geo::polygon_bounds(p)).collect();
+    let mut neighbours = vec![];
+    for i in 0..base.len() {
+        let mut near = vec![];
+        for j in (i + 1)..base.len() {
+            if mesh_geo::bounds_overlap(bounds[i], bounds[j]) {
+                near.push(j);
+            }
+        }
+        neighbours.push(near);
+    }
+    let pieces = base.iter().map(|_| Piece { obstacles: None, tris: vec![] }).collect();
+    return Window {
+        revision: map.revision,
+        base: base,
+        bounds: bounds,
+        neighbours: neighbours,
+        pos_to_z: pos_to_z,
+        pieces: pieces,
+        links: HashMap::new(),
+    };
+}
+
+fn clip(base: &Polygon<f64>, holes: &Vec<&Polygon<f64>>) -> Vec<Polygon<f64>> {
+    let mut polys = vec![base.clone()];
+    for hole in holes {
+        let mut next = vec![];
+        for p in polys {
+            if p.intersection(*hole, 1.).0.len() == 0 {
+                next.push(p);
+                continue;
+            }
+            for r in p.difference(*hole, 1.) {
+                next.push(r);
+            }
+        }
+        polys = next;
+    }
+    return polys;
+}
+
+fn piece_links(a: &Vec<mesh_geo::PrePoly>, b: &Vec<mesh_geo::PrePoly>, same: bool) -> Vec<LocalLink> {
+    let mut links = vec![];
+    for i in 0..a.len() {
+        let first = if same { i + 1 } else { 0 };
+        for j in first..b.len() {
+            match mesh_geo::local_link(&a[i], &b[j]) {
+                Some(weight) => links.push((i, j, weight)),
+                None => (),
+            }
+        }
+    }
+    return links;
+}
+
+// same result as create_local_graph, only re-clipping polygons whose nearby obstacles changed
+pub fn local_graph(
+    map_id: i64,
+    map: &mesh_geo::Map,
+    from: (i64, i64),
+    obstacles: &Vec<mesh_geo::LocalObstacle>,
+    options: &mesh_geo::LocalGraphOptions,
+) -> Option<(Graph<usize, (u64, mesh_geo::Line)>, Vec<mesh_geo::PrePoly>, Vec<NodeIndex>)> {
+    let mut tiles = mesh_geo::get_around_tiles(&map, from.0, from.1, 1260);
+    if tiles.len() == 0 {
+        return None;
+    }
+    tiles.sort();
+    let key = (map_id, tiles);
+
+    // taken out of the cache while in use so other windows are not blocked
+    let cached = WINDOWS.lock().unwrap().remove(&key);
+    let mut window = match cached {
+        Some(window) if window.revision == map.revision => window,
+        _ => new_window(map, &key.1),
+    };
+
+    let mut holes = vec![];
+    for obstacle in obstacles {
+        if mesh_geo::line_len((
+            (from.0 as i32, from.1 as i32, 0.0 as f32),
+            (obstacle.pos.0, obstacle.pos.1, 0.0 as f32),
+        )) > options.cutoff
+        {
+            continue;
+        }
+        let poly = mesh_geo::obstacle_polygon(obstacle, options);
+        let coords: Vec<(i64, i64)> = poly.exterior().0.iter().map(|c| (c.x as i64, c.y as i64)).collect();
+        holes.push((mesh_geo::polygon_bounds(&poly), poly, coords, obstacle.pos.2));
+    }
+
+    let mut dirty = vec![false; window.base.len()];
+    let pos_to_z = &window.pos_to_z;
+    for i in 0..window.base.len() {
+        let hits: Vec<usize> = (0..holes.len())
+            .filter(|h| mesh_geo::bounds_overlap(window.bounds[i], holes[*h].0))
+            .collect();
+        let mut hits_key: ObstacleKey = hits.iter().map(|h| holes[*h].2.clone()).collect();
+        hits_key.sort();
+        if window.pieces[i].obstacles.as_ref() == Some(&hits_key) {
+            continue;
+        }
+        dirty[i] = true;
+
+        let mut hole_z = HashMap::<(i32, i32), f32>::new();
+        for h in &hits {
+            for c in &holes[*h].2 {
+                hole_z.insert((c.0 as i32, c.1 as i32), holes[*h].3);
+            }
+        }
+        let clipped = clip(&window.base[i], &hits.iter().map(|h| &holes[*h].1).collect());
+        let mut tris = vec![];
+        for p in &clipped {
+            tris.extend(mesh_geo::local_tris(p, 0, |x, y| match hole_z.get(&(x, y)) {
+                Some(z) => *z,
+                None => match pos_to_z.get(&(x, y)) {
+                    Some(z) => *z,
+                    None => 0.0,
+                },
+            }));
+        }
+        window.pieces[i] = Piece { obstacles: Some(hits_key), tris: tris };
+    }
+
+    for i in 0..window.base.len() {
+        for j in std::iter::once(i).chain(window.neighbours[i].iter().cloned()) {
+            if !dirty[i] && !dirty[j] {
+                continue;
+            }
+            let links = piece_links(&window.pieces[i].tris, &window.pieces[j].tris, i == j);
+            window.links.insert((i, j), links);
+        }
+    }
+
+    // put the graph together from the pieces
+    let mut polys: Vec<mesh_geo::PrePoly> = vec![];
+    let mut offsets = vec![];
+    for piece in &window.pieces {
+        offsets.push(polys.len());
+        for tri in &piece.tris {
+            let mut tri = tri.clone();
+            tri.id = polys.len() as u64 + 1;
+            polys.push(tri);
+        }
+    }
+    let mut graph = Graph::<usize, (u64, mesh_geo::Line)>::new();
+    let mut nodes = vec![];
+    for i_poly in 0..polys.len() {
+        nodes.push(graph.add_node(i_poly));
+    }
+    let mut edges = vec![];
+    for ((i, j), links) in &window.links {
+        for (a, b, weight) in links {
+            let (na, nb) = (nodes[offsets[*i] + a], nodes[offsets[*j] + b]);
+            edges.push((na, nb, *weight));
+            edges.push((nb, na, *weight));
+        }
+    }
+    graph.extend_with_edges(edges);
+
+    let mut windows = WINDOWS.lock().unwrap();
+    if windows.len() >= MAX_WINDOWS {
+        windows.clear();
+    }
+    windows.insert(key, window);
+    return Some((graph, polys, nodes));
+}
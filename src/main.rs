@@ -16,6 +16,8 @@ use std::convert::TryInto;
 
 use std::collections::HashMap;
 
+mod heat_png;
+
 mod mesh_geo;
 
 mod waypoints;
@@ -205,6 +207,18 @@ fn main() -> io::Result<()> {
         }
     }
 
+    // heat around the start with a foe at the destination, for eyeballing the falloff
+    {
+        let foes = vec![mesh_geo::HeatSource {
+            pos: (dest.0 as i32, dest.1 as i32, 0.0),
+            radius: 1500.0,
+            weight: -3.0,
+            rings: 3,
+        }];
+        let values = mesh_geo::rasterize_heat(&MAP_1, &foes, (source.0 as f64, source.1 as f64), 256, 20.0);
+        heat_png::write_heat_png("heatmap.png", 256, &values)?;
+    }
+
     //use the grid to find the closest poly
 
     //let filename : &str = "../ex/data/mapdata/Elven_Ruins_NavTile.uexp.json";
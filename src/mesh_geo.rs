@@ -1,10 +1,11 @@
 use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
 use petgraph::Graph;
 
 use serde::{Deserialize, Serialize};
 use serde_json::Result;
 
-use std::collections::HashMap;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::fs;
 
 use std::cmp;
@@ -21,9 +22,20 @@ use geo_types::{Coordinate, LineString, MultiPolygon, Polygon};
 
 #[path = "algebra.rs"] mod algebra;
 #[path = "heat_map.rs"] mod heat_map;
+pub use self::heat_map::{
+    generate_heat_map_layout, heat_at, heat_cost_mul, overlay, polygon_area, HeatCost, HeatSource,
+    MAX_RINGS,
+};
 
 type EdgeWeight = (u64, Line);
 
+pub type Bounds = ((f64, f64), (f64, f64));
+
+// boundary segment with its unit normal pointing into the walkable side
+pub type Wall = ((f64, f64), (f64, f64), (f64, f64));
+
+const NODE_GRID_CELL: i64 = 1260;
+
 pub struct Map {
     pub extended_tiles: Vec<PreTile>,
     pub tiles: Vec<PreTile>,
@@ -34,6 +46,14 @@ pub struct Map {
     pub graph: Graph<(u64, PrePoly), EdgeWeight>,
     pub links: Vec<(u64, u64, Line)>,
     pub polygons: Vec<Vec<Polygon<f64>>>,
+    pub base_polygons: Vec<Vec<Polygon<f64>>>,
+    pub obstacles: HashMap<u64, Polygon<f64>>,
+    pub carved: HashMap<u64, Vec<PrePoly>>,
+    pub node_grid: HashMap<(i64, i64), Vec<NodeIndex<u32>>>,
+    pub walls: HashMap<u64, Vec<Wall>>,
+    pub wall_grid: HashMap<(i64, i64), Vec<u64>>,
+    // bumped whenever the graph is rebuilt, so caches know they are stale
+    pub revision: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
@@ -78,7 +98,7 @@ pub struct PrePoly {
     pub id: u64,
 }
 
-pub type PrePolyWithHeat = (PrePoly, i32);
+pub type PrePolyWithHeat = (PrePoly, f64);
 
 pub type Line = ((i32, i32, f32), (i32, i32, f32));
 
@@ -94,6 +114,25 @@ pub fn point_inside_poly(x: i32, y: i32, poly: &PrePoly) -> i32 {
     return algebra::point_inside_poly((x as f64, y as f64), &polygon);
 }
 
+// inclusive of the border, works for any vertex count unlike point_inside_poly
+pub fn poly_contains(poly: &PrePoly, x: f64, y: f64) -> bool {
+    let mut inside = false;
+    let mut j = poly.verts.len() - 1;
+    for i in 0..poly.verts.len() {
+        let (xi, yi) = (poly.verts[i].0 as f64, poly.verts[i].1 as f64);
+        let (xj, yj) = (poly.verts[j].0 as f64, poly.verts[j].1 as f64);
+        let cross = (xj - xi) * (y - yi) - (yj - yi) * (x - xi);
+        if cross == 0.0 && x >= xi.min(xj) && x <= xi.max(xj) && y >= yi.min(yj) && y <= yi.max(yj) {
+            return true;
+        }
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    return inside;
+}
+
 pub fn poly_area(poly: &PrePoly) -> f64 {
     // Initialze area
     let mut area: f64 = 0.0;
@@ -107,6 +146,64 @@ pub fn poly_area(poly: &PrePoly) -> f64 {
     return area.abs();
 }
 
+pub fn get_z_from_poly(x: i32, y: i32, poly: &PrePoly) -> f32 {
+    let v1 = (
+        poly.verts[0].0 as f64,
+        poly.verts[0].1 as f64,
+        poly.verts[0].2 as f64,
+    );
+    let v2 = (
+        poly.verts[1].0 as f64,
+        poly.verts[1].1 as f64,
+        poly.verts[1].2 as f64,
+    );
+    let v3 = (
+        poly.verts[2].0 as f64,
+        poly.verts[2].1 as f64,
+        poly.verts[2].2 as f64,
+    );
+
+    let a = v1.1 * (v2.2 - v3.2) + v2.1 * (v3.2 - v1.2) + v3.1 * (v1.2 - v2.2);
+    let b = v1.2 * (v2.0 - v3.0) + v2.2 * (v3.0 - v1.0) + v3.2 * (v1.0 - v2.0);
+    let c = v1.0 * (v2.1 - v3.1) + v2.0 * (v3.1 - v1.1) + v3.0 * (v1.1 - v2.1);
+    let d = v1.0 * (v2.1 * v3.2 - v3.1 * v2.2)
+        + v2.0 * (v3.1 * v1.2 - v1.1 * v3.2)
+        + v3.0 * (v1.1 * v2.2 - v2.1 * v1.2);
+
+    let z = (d - (a * x as f64) - (b * y as f64)) / c;
+    return z as f32;
+}
+
+pub fn prepoly_to_polygon(poly: &PrePoly) -> Polygon<f64> {
+    let mut lines: Vec<Coordinate<f64>> = poly
+        .verts
+        .iter()
+        .map(|v| Coordinate {
+            x: v.0 as f64,
+            y: v.1 as f64,
+        })
+        .collect();
+    lines.push(Coordinate {
+        x: poly.verts[0].0 as f64,
+        y: poly.verts[0].1 as f64,
+    });
+    return Polygon::new(LineString(lines), vec![]);
+}
+
+pub fn polygon_bounds(poly: &Polygon<f64>) -> Bounds {
+    let mut min = (std::f64::MAX, std::f64::MAX);
+    let mut max = (std::f64::MIN, std::f64::MIN);
+    for c in &poly.exterior().0 {
+        min = (min.0.min(c.x), min.1.min(c.y));
+        max = (max.0.max(c.x), max.1.max(c.y));
+    }
+    return (min, max);
+}
+
+pub fn bounds_overlap(a: Bounds, b: Bounds) -> bool {
+    return (a.0).0 <= (b.1).0 && (b.0).0 <= (a.1).0 && (a.0).1 <= (b.1).1 && (b.0).1 <= (a.1).1;
+}
+
 
 pub fn is_intersect_polys(poly1: &PrePoly, poly2: &PrePoly) -> bool {
     let mut lines1 = vec![];
@@ -170,6 +267,27 @@ pub fn merge_two_polygon(p1: &Polygon<f64>, p2: &Polygon<f64>) -> Option<Polygon
     return Some(polys[0].clone());
 }
 
+// greedy union, polys sorted by distance from one start mostly touch an earlier one and merge on the first try
+pub fn merge_polygons(polys: Vec<Polygon<f64>>) -> Vec<Polygon<f64>> {
+    let mut merged: Vec<Polygon<f64>> = vec![];
+    for p in polys {
+        let mut current = p;
+        let mut i = 0;
+        while i < merged.len() {
+            match merge_two_polygon(&merged[i], &current) {
+                Some(new_poly) => {
+                    current = new_poly;
+                    merged.remove(i);
+                    i = 0;
+                }
+                None => i = i + 1,
+            }
+        }
+        merged.push(current);
+    }
+    return merged;
+}
+
 pub fn dedup_coordinates(pts: Vec<Coordinate<f64>>) -> Vec<Coordinate<f64>> {
     let mut points: Vec<Coordinate<f64>> = pts.clone();
 
@@ -767,22 +885,20 @@ pub fn files_to_map<'a>(files: Vec<String>, _outname: &str) -> Map {
             let bn = *name_to_node.get(b).unwrap();
             let (_, p1) = &graph[an];
             let (_, p2) = &graph[bn];
-            let dx = p1.center.0 - p2.center.0;
-            let dy = p1.center.1 - p2.center.1;
-            let d = ((dx * dx + dy * dy) as f64).sqrt().round() as u64;
-            let weight_mul = match line_len(*line) {
-                0..=100 => 3.2,
-                101..=200 => 2.1,
-                _ => 1.0,
-            };
-            let weight = ((d as f64) * weight_mul).trunc() as u64;
+            let weight = link_weight(p1, p2, *line);
             return (an, bn, (weight, line.clone()));
         })
         .collect();
 
     graph.extend_with_edges(edges);
+    let node_grid = build_node_grid(&graph);
+    let (walls, wall_grid) = build_walls(&graph);
 
     return Map {
+        node_grid: node_grid,
+        walls: walls,
+        wall_grid: wall_grid,
+        revision: 0,
         graph: graph,
         bounds: (Point { x: min_x, y: min_y }, Point { x: max_x, y: max_y }),
         tiles: tiles2,
@@ -791,8 +907,27 @@ pub fn files_to_map<'a>(files: Vec<String>, _outname: &str) -> Map {
         tiles_cols: cols,
         tiles_rows: rows,
         links: links,
+        base_polygons: polygons.clone(),
         polygons: polygons,
+        obstacles: HashMap::new(),
+        carved: HashMap::new(),
+    };
+}
+
+pub fn center_dist(p1: &PrePoly, p2: &PrePoly) -> u64 {
+    let dx = p1.center.0 - p2.center.0;
+    let dy = p1.center.1 - p2.center.1;
+    return ((dx * dx + dy * dy) as f64).sqrt().round() as u64;
+}
+
+pub fn link_weight(p1: &PrePoly, p2: &PrePoly, line: Line) -> u64 {
+    let d = center_dist(p1, p2);
+    let weight_mul = match line_len(line) {
+        0..=100 => 3.2,
+        101..=200 => 2.1,
+        _ => 1.0,
     };
+    return ((d as f64) * weight_mul).trunc() as u64;
 }
 
 pub fn line_len((a, b): Line) -> u64 {
@@ -967,6 +1102,508 @@ pub fn find_closest_node(
     return closest;
 }
 
+// links are stored both ways with the same weight, so this also gives costs towards the starts
+pub fn dijkstra_bounded<F>(
+    graph: &Graph<(u64, PrePoly), EdgeWeight>,
+    starts: &Vec<NodeIndex<u32>>,
+    max_cost: u64,
+    stop: F,
+) -> HashMap<NodeIndex<u32>, (u64, Option<NodeIndex<u32>>)>
+where
+    F: FnMut(NodeIndex<u32>) -> bool,
+{
+    return dijkstra_bounded_by(graph, starts, max_cost, |_, _, weight| weight.0, stop);
+}
+
+// same search with the cost of each edge given by edge_cost(from, to, weight)
+pub fn dijkstra_bounded_by<C, F>(
+    graph: &Graph<(u64, PrePoly), EdgeWeight>,
+    starts: &Vec<NodeIndex<u32>>,
+    max_cost: u64,
+    mut edge_cost: C,
+    mut stop: F,
+) -> HashMap<NodeIndex<u32>, (u64, Option<NodeIndex<u32>>)>
+where
+    C: FnMut(NodeIndex<u32>, NodeIndex<u32>, &EdgeWeight) -> u64,
+    F: FnMut(NodeIndex<u32>) -> bool,
+{
+    let mut best = HashMap::<NodeIndex<u32>, (u64, Option<NodeIndex<u32>>)>::new();
+    let mut settled = HashMap::<NodeIndex<u32>, (u64, Option<NodeIndex<u32>>)>::new();
+    let mut heap = BinaryHeap::new();
+    for s in starts {
+        best.insert(*s, (0, None));
+        heap.push(cmp::Reverse((0, *s)));
+    }
+
+    while let Some(cmp::Reverse((cost, node))) = heap.pop() {
+        if settled.contains_key(&node) {
+            continue;
+        }
+        settled.insert(node, best[&node]);
+        if stop(node) {
+            break;
+        }
+        for edge in graph.edges(node) {
+            let next = edge.target();
+            let next_cost = cost + edge_cost(node, next, edge.weight());
+            if next_cost > max_cost || settled.contains_key(&next) {
+                continue;
+            }
+            match best.get(&next) {
+                Some((c, _)) if *c <= next_cost => continue,
+                _ => (),
+            }
+            best.insert(next, (next_cost, Some(node)));
+            heap.push(cmp::Reverse((next_cost, next)));
+        }
+    }
+    return settled;
+}
+
+// node chain from the search start to node, following dijkstra_bounded parents
+pub fn dijkstra_chain(
+    visited: &HashMap<NodeIndex<u32>, (u64, Option<NodeIndex<u32>>)>,
+    node: NodeIndex<u32>,
+) -> Vec<NodeIndex<u32>> {
+    let mut nodes = vec![node];
+    while let Some((_, Some(parent))) = visited.get(&nodes[nodes.len() - 1]) {
+        nodes.push(*parent);
+    }
+    nodes.reverse();
+    return nodes;
+}
+
+// polygons within max_dist of walking between polygon centers, without the narrow portal
+// penalty of the path weights, nearest first
+pub fn reachable_polys(map: &Map, from: (i64, i64), max_dist: u64) -> Vec<(NodeIndex<u32>, u64)> {
+    let g1 = find_closest_node(&map.graph, from.0, from.1);
+    let visited = dijkstra_bounded_by(
+        &map.graph,
+        &vec![g1],
+        max_dist,
+        |a, b, _| center_dist(&map.graph[a].1, &map.graph[b].1),
+        |_| false,
+    );
+    let mut reached: Vec<(NodeIndex<u32>, u64)> = visited
+        .iter()
+        .map(|(node, (cost, _))| (*node, *cost))
+        .collect();
+    reached.sort_by_key(|(_, cost)| *cost);
+    return reached;
+}
+
+// corridor to the polygon within distance whose walking distance to the closest threat is largest
+pub fn flee_corridor(
+    map: &Map,
+    from: (i64, i64),
+    threats: &Vec<(i64, i64)>,
+    distance: u64,
+) -> Vec<NodeIndex<u32>> {
+    let g1 = find_closest_node(&map.graph, from.0, from.1);
+    let reach = dijkstra_bounded(&map.graph, &vec![g1], distance, |_| false);
+
+    // past this bound every polygon is equally safe and straight distance decides
+    let threat_bound = distance * 4;
+    let threat_nodes: Vec<NodeIndex<u32>> = threats
+        .iter()
+        .map(|t| find_closest_node(&map.graph, t.0, t.1))
+        .collect();
+    let from_threats = dijkstra_bounded(&map.graph, &threat_nodes, threat_bound, |_| false);
+
+    let mut best = g1;
+    let mut best_score = (0, 0);
+    for node in reach.keys() {
+        let threat_cost = match from_threats.get(node) {
+            Some((cost, _)) => *cost,
+            None => threat_bound,
+        };
+        let mut closest = std::i64::MAX;
+        for t in threats {
+            closest = cmp::min(closest, hdist(&map.graph[*node], t.0, t.1));
+        }
+        let score = (threat_cost, closest);
+        if score > best_score {
+            best = *node;
+            best_score = score;
+        }
+    }
+    return dijkstra_chain(&reach, best);
+}
+
+// grid cells touched by the bounds
+fn grid_cells(bounds: Bounds) -> Vec<(i64, i64)> {
+    let (min, max) = bounds;
+    let c0 = ((min.0 as i64).div_euclid(NODE_GRID_CELL), (min.1 as i64).div_euclid(NODE_GRID_CELL));
+    let c1 = ((max.0 as i64).div_euclid(NODE_GRID_CELL), (max.1 as i64).div_euclid(NODE_GRID_CELL));
+    let mut cells = vec![];
+    for cx in c0.0..(c1.0 + 1) {
+        for cy in c0.1..(c1.1 + 1) {
+            cells.push((cx, cy));
+        }
+    }
+    return cells;
+}
+
+// graph nodes by the grid cells their bounds touch, for point lookups
+pub fn build_node_grid(graph: &Graph<(u64, PrePoly), EdgeWeight>) -> HashMap<(i64, i64), Vec<NodeIndex<u32>>> {
+    let mut node_grid = HashMap::<(i64, i64), Vec<NodeIndex<u32>>>::new();
+    for node in graph.node_indices() {
+        for cell in grid_cells(polygon_bounds(&prepoly_to_polygon(&graph[node].1))) {
+            node_grid.entry(cell).or_insert(vec![]).push(node);
+        }
+    }
+    return node_grid;
+}
+
+pub fn find_node_at(map: &Map, x: f64, y: f64) -> Option<NodeIndex<u32>> {
+    let cell = ((x as i64).div_euclid(NODE_GRID_CELL), (y as i64).div_euclid(NODE_GRID_CELL));
+    match map.node_grid.get(&cell) {
+        Some(nodes) => {
+            for node in nodes {
+                if poly_contains(&map.graph[*node].1, x, y) {
+                    return Some(*node);
+                }
+            }
+        }
+        None => (),
+    }
+    return None;
+}
+
+pub struct RaycastHit {
+    pub hit: bool,
+    pub point: (f64, f64, f32),
+    // unit normal of the wall that was hit, facing back towards the ray start
+    pub normal: (f64, f64),
+    pub visited: Vec<NodeIndex<u32>>,
+}
+
+fn cross2(a: (f64, f64), b: (f64, f64)) -> f64 {
+    return a.0 * b.1 - a.1 * b.0;
+}
+
+// closest point to p on the segment a-b and its distance
+fn closest_on_segment(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> ((f64, f64), f64) {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx * dx + dy * dy;
+    let mut t = 0.0;
+    if len2 > 0.0 {
+        t = (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len2).max(0.0).min(1.0);
+    }
+    let c = (a.0 + dx * t, a.1 + dy * t);
+    return (c, (c.0 - p.0).hypot(c.1 - p.1));
+}
+
+fn line_ends(line: Line) -> ((f64, f64), (f64, f64)) {
+    return (((line.0).0 as f64, (line.0).1 as f64), ((line.1).0 as f64, (line.1).1 as f64));
+}
+
+fn point_on_line(line: Line, p: (f64, f64), tolerance: f64) -> bool {
+    let (a, b) = line_ends(line);
+    return closest_on_segment(p, a, b).1 <= tolerance;
+}
+
+// walks the polygons along from -> to over shared portals, Detour style
+pub fn raycast(map: &Map, from: (f64, f64), to: (f64, f64)) -> Option<RaycastHit> {
+    let start = match find_node_at(map, from.0, from.1) {
+        Some(node) => node,
+        None => return None,
+    };
+    let dir = (to.0 - from.0, to.1 - from.1);
+    let mut current = start;
+    let mut visited = vec![start];
+    let mut seen = HashSet::new();
+    seen.insert(start);
+    loop {
+        let poly = &map.graph[current].1;
+        if poly_contains(poly, to.0, to.1) {
+            let z = get_z_from_poly(to.0 as i32, to.1 as i32, poly);
+            return Some(RaycastHit { hit: false, point: (to.0, to.1, z), normal: (0.0, 0.0), visited: visited });
+        }
+
+        // the edge the ray leaves through is the one crossed last
+        let mut exit: Option<(f64, (f64, f64), (f64, f64))> = None;
+        let n = poly.verts.len();
+        for i in 0..n {
+            let a = (poly.verts[i].0 as f64, poly.verts[i].1 as f64);
+            let b = (poly.verts[(i + 1) % n].0 as f64, poly.verts[(i + 1) % n].1 as f64);
+            let e = (b.0 - a.0, b.1 - a.1);
+            let denom = cross2(dir, e);
+            if denom.abs() < 1e-9 {
+                continue;
+            }
+            let ap = (a.0 - from.0, a.1 - from.1);
+            let t = cross2(ap, e) / denom;
+            let u = cross2(ap, dir) / denom;
+            if t < 0.0 || t > 1.0 || u < -1e-6 || u > 1.0 + 1e-6 {
+                continue;
+            }
+            match exit {
+                Some((best, _, _)) if best >= t => (),
+                _ => exit = Some((t, a, b)),
+            }
+        }
+        let (t, a, b) = match exit {
+            Some(exit) => exit,
+            None => (0.0, from, from),
+        };
+        let p = (from.0 + dir.0 * t, from.1 + dir.1 * t);
+
+        // a neighbour sharing the exit point, preferring the one the ray continues into
+        let ahead = (p.0 + dir.0 * 1e-3, p.1 + dir.1 * 1e-3);
+        let mut next = None;
+        for edge in map.graph.edges(current) {
+            let other = if edge.source() == current { edge.target() } else { edge.source() };
+            if seen.contains(&other) || !point_on_line((edge.weight()).1, p, 1.0) {
+                continue;
+            }
+            if poly_contains(&map.graph[other].1, ahead.0, ahead.1) {
+                next = Some(other);
+                break;
+            }
+            if next == None {
+                next = Some(other);
+            }
+        }
+
+        match next {
+            Some(node) => {
+                seen.insert(node);
+                visited.push(node);
+                current = node;
+            }
+            None => {
+                let mut normal = (-(b.1 - a.1), b.0 - a.0);
+                let len = normal.0.hypot(normal.1);
+                if len > 0.0 {
+                    normal = (normal.0 / len, normal.1 / len);
+                }
+                if normal.0 * dir.0 + normal.1 * dir.1 > 0.0 {
+                    normal = (-normal.0, -normal.1);
+                }
+                let z = get_z_from_poly(p.0 as i32, p.1 as i32, poly);
+                return Some(RaycastHit { hit: true, point: (p.0, p.1, z), normal: normal, visited: visited });
+            }
+        }
+    }
+}
+
+// slides from -> to along the walls, returns the furthest reachable position and the polygons on the way
+pub fn move_along_surface(map: &Map, from: (f64, f64), to: (f64, f64)) -> Option<((f64, f64, f32), Vec<NodeIndex<u32>>)> {
+    let start = match find_node_at(map, from.0, from.1) {
+        Some(node) => node,
+        None => return None,
+    };
+    // only polygons touching the circle around the move are searched
+    let center = ((from.0 + to.0) / 2.0, (from.1 + to.1) / 2.0);
+    let radius = (to.0 - from.0).hypot(to.1 - from.1) / 2.0 + 1.0;
+
+    let mut parents = HashMap::<NodeIndex<u32>, Option<NodeIndex<u32>>>::new();
+    parents.insert(start, None);
+    let mut queue = std::collections::VecDeque::new();
+    queue.push_back(start);
+    let mut best = (from, std::f64::MAX, start);
+    while let Some(node) = queue.pop_front() {
+        let poly = &map.graph[node].1;
+        if poly_contains(poly, to.0, to.1) {
+            best = (to, 0.0, node);
+            break;
+        }
+        let n = poly.verts.len();
+        for i in 0..n {
+            let a = (poly.verts[i].0 as f64, poly.verts[i].1 as f64);
+            let b = (poly.verts[(i + 1) % n].0 as f64, poly.verts[(i + 1) % n].1 as f64);
+            let (c, d) = closest_on_segment(to, a, b);
+            if d < best.1 {
+                best = (c, d, node);
+            }
+        }
+        for edge in map.graph.edges(node) {
+            let other = if edge.source() == node { edge.target() } else { edge.source() };
+            if parents.contains_key(&other) {
+                continue;
+            }
+            let (a, b) = line_ends((edge.weight()).1);
+            if closest_on_segment(center, a, b).1 > radius {
+                continue;
+            }
+            parents.insert(other, Some(node));
+            queue.push_back(other);
+        }
+    }
+
+    let (point, _, node) = best;
+    let z = get_z_from_poly(point.0 as i32, point.1 as i32, &map.graph[node].1);
+    let mut visited = vec![node];
+    let mut current = node;
+    while let Some(Some(parent)) = parents.get(&current) {
+        visited.push(*parent);
+        current = *parent;
+    }
+    visited.reverse();
+    return Some(((point.0, point.1, z), visited));
+}
+
+// polygon edges, minus the parts covered by portals
+fn node_walls(graph: &Graph<(u64, PrePoly), EdgeWeight>, node: NodeIndex<u32>) -> Vec<Wall> {
+    let mut walls = vec![];
+    let poly = &graph[node].1;
+    let center = (poly.center.0 as f64, poly.center.1 as f64);
+    let portals: Vec<((f64, f64), (f64, f64))> = graph.edges(node).map(|e| line_ends((e.weight()).1)).collect();
+    let n = poly.verts.len();
+    for i in 0..n {
+        let a = (poly.verts[i].0 as f64, poly.verts[i].1 as f64);
+        let b = (poly.verts[(i + 1) % n].0 as f64, poly.verts[(i + 1) % n].1 as f64);
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let len = dx.hypot(dy);
+        if len == 0.0 {
+            continue;
+        }
+        // parts of the edge, as 0..1 ranges, shared with a neighbour
+        let mut covered = vec![];
+        for (p, q) in &portals {
+            if closest_on_segment(*p, a, b).1 > 1.0 || closest_on_segment(*q, a, b).1 > 1.0 {
+                continue;
+            }
+            let tp = ((p.0 - a.0) * dx + (p.1 - a.1) * dy) / (len * len);
+            let tq = ((q.0 - a.0) * dx + (q.1 - a.1) * dy) / (len * len);
+            covered.push((tp.min(tq), tp.max(tq)));
+        }
+        covered.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap());
+
+        let mut normal = (-dy / len, dx / len);
+        if (center.0 - a.0) * normal.0 + (center.1 - a.1) * normal.1 < 0.0 {
+            normal = (-normal.0, -normal.1);
+        }
+        let mut t = 0.0;
+        for (t0, t1) in covered.iter().chain([(1.0, 1.0)].iter()) {
+            if (t0 - t) * len > 1.0 {
+                walls.push(((a.0 + dx * t, a.1 + dy * t), (a.0 + dx * t0, a.1 + dy * t0), normal));
+            }
+            t = t.max(*t1);
+        }
+    }
+    return walls;
+}
+
+fn wall_cells(poly_walls: &Vec<Wall>) -> HashSet<(i64, i64)> {
+    let mut cells = HashSet::new();
+    for (a, b, _) in poly_walls {
+        for cell in grid_cells(((a.0.min(b.0), a.1.min(b.1)), (a.0.max(b.0), a.1.max(b.1)))) {
+            cells.insert(cell);
+        }
+    }
+    return cells;
+}
+
+fn insert_walls(
+    walls: &mut HashMap<u64, Vec<Wall>>,
+    wall_grid: &mut HashMap<(i64, i64), Vec<u64>>,
+    id: u64,
+    poly_walls: Vec<Wall>,
+) {
+    for cell in wall_cells(&poly_walls) {
+        wall_grid.entry(cell).or_insert(vec![]).push(id);
+    }
+    walls.insert(id, poly_walls);
+}
+
+fn remove_walls(walls: &mut HashMap<u64, Vec<Wall>>, wall_grid: &mut HashMap<(i64, i64), Vec<u64>>, id: u64) {
+    match walls.remove(&id) {
+        Some(poly_walls) => {
+            for cell in wall_cells(&poly_walls) {
+                match wall_grid.get_mut(&cell) {
+                    Some(ids) => ids.retain(|i| *i != id),
+                    None => (),
+                }
+            }
+        }
+        None => (),
+    }
+}
+
+// walls of every polygon by polygon id, and the polygon ids by the grid cells their walls touch
+pub fn build_walls(
+    graph: &Graph<(u64, PrePoly), EdgeWeight>,
+) -> (HashMap<u64, Vec<Wall>>, HashMap<(i64, i64), Vec<u64>>) {
+    let mut walls = HashMap::<u64, Vec<Wall>>::new();
+    let mut wall_grid = HashMap::<(i64, i64), Vec<u64>>::new();
+    for node in graph.node_indices() {
+        insert_walls(&mut walls, &mut wall_grid, graph[node].0, node_walls(graph, node));
+    }
+    return (walls, wall_grid);
+}
+
+// largest wall query radius, two grid cells each way; bigger radii are clamped to it
+pub const MAX_WALL_RADIUS: f64 = 2520.0;
+
+// every wall within max_radius: distance, closest point and wall normal
+pub fn walls_near(map: &Map, x: f64, y: f64, max_radius: f64) -> Vec<(f64, (f64, f64), (f64, f64))> {
+    let mut seen = HashSet::new();
+    let mut walls = vec![];
+    if !max_radius.is_finite() || max_radius < 0.0 || !x.is_finite() || !y.is_finite() {
+        return walls;
+    }
+    let max_radius = max_radius.min(MAX_WALL_RADIUS);
+    for cell in grid_cells(((x - max_radius, y - max_radius), (x + max_radius, y + max_radius))) {
+        match map.wall_grid.get(&cell) {
+            Some(ids) => {
+                for id in ids {
+                    if !seen.insert(*id) {
+                        continue;
+                    }
+                    for (a, b, normal) in &map.walls[id] {
+                        let (point, d) = closest_on_segment((x, y), *a, *b);
+                        if d <= max_radius {
+                            walls.push((d, point, *normal));
+                        }
+                    }
+                }
+            }
+            None => (),
+        }
+    }
+    return walls;
+}
+
+// closest wall within max_radius: distance, closest point and wall normal
+pub fn distance_to_wall(map: &Map, x: f64, y: f64, max_radius: f64) -> Option<(f64, (f64, f64), (f64, f64))> {
+    let mut best: Option<(f64, (f64, f64), (f64, f64))> = None;
+    for (d, point, normal) in walls_near(map, x, y, max_radius) {
+        match best {
+            Some((best_d, _, _)) if best_d <= d => (),
+            _ => best = Some((d, point, normal)),
+        }
+    }
+    return best;
+}
+
+// largest raster side, a 512 x 512 grid is 1 MiB of f32
+pub const MAX_RASTER_CELLS: usize = 512;
+
+// heat sampled at cell centers of a cells x cells grid around center, row by row from the
+// lowest x and y; cells off the navmesh are NaN
+pub fn rasterize_heat(map: &Map, sources: &Vec<HeatSource>, center: (f64, f64), cells: usize, cell_size: f64) -> Vec<f32> {
+    let origin = (
+        center.0 - cell_size * cells as f64 / 2.0,
+        center.1 - cell_size * cells as f64 / 2.0,
+    );
+    let mut values = Vec::with_capacity(cells * cells);
+    for row in 0..cells {
+        for col in 0..cells {
+            let x = origin.0 + (col as f64 + 0.5) * cell_size;
+            let y = origin.1 + (row as f64 + 0.5) * cell_size;
+            if find_node_at(map, x, y).is_none() {
+                values.push(std::f32::NAN);
+                continue;
+            }
+            let heat: f64 = sources.iter().map(|s| heat_at(s, x, y)).sum();
+            values.push(heat as f32);
+        }
+    }
+    return values;
+}
+
 pub fn get_around_polys(map: &Map, x: i64, y: i64, range: i64) -> Vec<&PrePoly> {
     let dist: i64 = range * range;
     let mut polys = vec![];
@@ -1057,10 +1694,83 @@ pub fn find_closest_idx_0(polys: &Vec<PrePolyWithHeat>, x: i64, y: i64) -> usize
 
 
 
+pub enum ObstacleShape {
+    Circle(f64),
+    // half width, half height, rotation in radians
+    OrientedBox(f64, f64, f64),
+}
+
+pub struct LocalObstacle {
+    pub pos: (i32, i32, f32),
+    pub shape: ObstacleShape,
+}
+
+pub struct LocalGraphOptions {
+    // sides of the polygon a circle is approximated with, at most MAX_CIRCLE_SEGMENTS
+    pub circle_segments: usize,
+    // radius of the moving agent, added around every obstacle
+    pub agent_radius: f64,
+    // obstacles further than this from the start are ignored, at most MAX_LOCAL_CUTOFF
+    pub cutoff: u64,
+}
+
+// the local graph only spans the tiles around the start, obstacles further away never reach it
+pub const MAX_LOCAL_CUTOFF: u64 = 1260;
+
+// every obstacle side is clipped against the tiles, more sides only cost time
+pub const MAX_CIRCLE_SEGMENTS: usize = 64;
+
+impl Default for LocalGraphOptions {
+    fn default() -> LocalGraphOptions {
+        return LocalGraphOptions {
+            circle_segments: 8,
+            agent_radius: 0.0,
+            cutoff: 1200,
+        };
+    }
+}
+
+// the old fixed 60x60 square around a player
+pub fn player_obstacle(pos: (i32, i32, f32)) -> LocalObstacle {
+    return LocalObstacle {
+        pos: pos,
+        shape: ObstacleShape::OrientedBox(30.0, 30.0, 0.0),
+    };
+}
+
+pub fn obstacle_polygon(obstacle: &LocalObstacle, options: &LocalGraphOptions) -> Polygon<f64> {
+    let (x, y) = (obstacle.pos.0 as f64, obstacle.pos.1 as f64);
+    let mut coords = vec![];
+    match obstacle.shape {
+        ObstacleShape::Circle(radius) => {
+            let n = cmp::max(options.circle_segments, 3);
+            // push the corners out so the sides still clear the circle
+            let r = (radius + options.agent_radius) / (std::f64::consts::PI / n as f64).cos();
+            for i in 0..n {
+                let a = 2.0 * std::f64::consts::PI * i as f64 / n as f64;
+                coords.push(Coordinate { x: (x + r * a.cos()).round(), y: (y + r * a.sin()).round() });
+            }
+        }
+        ObstacleShape::OrientedBox(half_w, half_h, angle) => {
+            let (hw, hh) = (half_w + options.agent_radius, half_h + options.agent_radius);
+            let (sin, cos) = angle.sin_cos();
+            for (dx, dy) in [(-hw, -hh), (-hw, hh), (hw, hh), (hw, -hh)].iter() {
+                coords.push(Coordinate {
+                    x: (x + dx * cos - dy * sin).round(),
+                    y: (y + dx * sin + dy * cos).round(),
+                });
+            }
+        }
+    }
+    coords.push(coords[0]);
+    return Polygon::new(LineString(coords), vec![]);
+}
+
 pub fn create_local_graph(
     map: &Map,
     from: (i64, i64),
-    players: &Vec<(i32, i32, f32)>,
+    obstacles: &Vec<LocalObstacle>,
+    options: &LocalGraphOptions,
 ) -> Option<(Graph<usize, (u64, Line)>, Vec<PrePoly>, Vec<NodeIndex>)> {
     let mut pos_to_z = HashMap::<(i32, i32), f32>::new();
     let mut tiles = get_around_tiles(&map, from.0, from.1, 1260);
@@ -1082,36 +1792,20 @@ pub fn create_local_graph(
         }
     }
 
-    // make players as holes and keep player's Z pos *** players supposed are in radus 1200
-    for player in players {
+    // make obstacles as holes and keep their Z pos
+    for obstacle in obstacles {
         if line_len((
             (from.0 as i32, from.1 as i32, 0.0 as f32),
-            (player.0, player.1, 0.0 as f32),
-        )) > 1200
+            (obstacle.pos.0, obstacle.pos.1, 0.0 as f32),
+        )) > options.cutoff
         {
             continue;
         }
 
-        let x1 = (player.0 - 30) as f64;
-        let x2 = (player.0 + 30) as f64;
-        let y1 = (player.1 - 30) as f64;
-        let y2 = (player.1 + 30) as f64;
-
-        pos_to_z.insert((x1 as i32, y1 as i32), player.2);
-        pos_to_z.insert((x1 as i32, y2 as i32), player.2);
-        pos_to_z.insert((x2 as i32, y2 as i32), player.2);
-        pos_to_z.insert((x2 as i32, y1 as i32), player.2);
-
-        let player_hole = Polygon::new(
-            LineString(vec![
-                Coordinate { x: x1, y: y1 },
-                Coordinate { x: x1, y: y2 },
-                Coordinate { x: x2, y: y2 },
-                Coordinate { x: x2, y: y1 },
-                Coordinate { x: x1, y: y1 },
-            ]),
-            vec![],
-        );
+        let player_hole = obstacle_polygon(obstacle, options);
+        for c in player_hole.exterior().clone().into_iter() {
+            pos_to_z.insert((c.x as i32, c.y as i32), obstacle.pos.2);
+        }
 
         for i_poly in (0..polys.len()).rev() {
             let res = polys[i_poly].intersection(&player_hole, 1.);
@@ -1134,39 +1828,14 @@ pub fn create_local_graph(
     // println!("made all holes");
 
     // split into triangles
-    let mut poly_id: u64 = 1;
     let mut new_polys: Vec<PrePoly> = vec![];
     for new_p in &polys {
-        let tris = algebra::polygon2tris(new_p);
-        for tri in &tris{
-            let mut ptts: Vec<(i32, i32, f32)> = vec![];
-            ptts.push( ((tri.0).0 as i32, (tri.0).1 as i32, 0.) );
-            ptts.push( ((tri.1).0 as i32, (tri.1).1 as i32, 0.));
-            ptts.push( ((tri.2).0 as i32, (tri.2).1 as i32, 0.));
-            for i in 0..3{
-                let mut z: f32 = 0.0;
-                match pos_to_z.get(&(ptts[i].0, ptts[i].1)) {
-                    Some(z_) => {
-                        z = *z_;
-                    }
-                    None => (),
-                }
-                ptts[i].2 = z;
-            }
-
-            let center = (
-                (ptts[0].0 + ptts[1].0 + ptts[2].0) / 3,
-                (ptts[0].1 + ptts[1].1 + ptts[2].1) / 3,
-            );
-            let n_p = PrePoly {
-                vert_count: 3,
-                verts: ptts,
-                center: center,
-                id: poly_id,
-            };
-            new_polys.insert(new_polys.len(), n_p);
-            poly_id = poly_id + 1;
-        }
+        let first_id = new_polys.len() as u64 + 1;
+        let tris = local_tris(new_p, first_id, |x, y| match pos_to_z.get(&(x, y)) {
+            Some(z) => *z,
+            None => 0.0,
+        });
+        new_polys.extend(tris);
     }
 
     // println!("splited all tris");
@@ -1181,28 +1850,11 @@ pub fn create_local_graph(
 
     //make link
     for i_poly in 0..new_polys.len() {
-        // for j_poly in 0..new_polys.len() {
-        for j_poly in (i_poly + 1)..new_polys.len() {            
-            if i_poly == j_poly {
-                continue;
-            }
-            match area_intersect(&new_polys[i_poly], &new_polys[j_poly], false) {
-                Some(edge) => {
-                    let dx = new_polys[i_poly].center.0 - new_polys[j_poly].center.0;
-                    let dy = new_polys[i_poly].center.1 - new_polys[j_poly].center.1;
-                    let d = ((dx * dx + dy * dy) as f64).sqrt().round() as u64;
-                    let edge_len = line_len(edge);
-                    let weight_mul = match edge_len {
-                        0..=60 => 1000.,
-                        61..=100 => 100.,
-                        101..=200 => 10.1,
-                        0..=100 => 3.2,
-                        101..=200 => 2.1,
-                        _ => 1.0,
-                    };
-                    let weight = ((d as f64) * weight_mul).trunc() as u64;
-                    links.push((nodes[i_poly], nodes[j_poly], (weight, edge)));
-                    links.push((nodes[j_poly], nodes[i_poly], (weight, edge)));
+        for j_poly in (i_poly + 1)..new_polys.len() {
+            match local_link(&new_polys[i_poly], &new_polys[j_poly]) {
+                Some(weight) => {
+                    links.push((nodes[i_poly], nodes[j_poly], weight));
+                    links.push((nodes[j_poly], nodes[i_poly], weight));
                 }
                 None => (),
             }
@@ -1212,53 +1864,116 @@ pub fn create_local_graph(
     return Some((graph, new_polys, nodes));
 }
 
+// triangles of a clipped local polygon, ids counting up from first_id
+pub fn local_tris<F>(poly: &Polygon<f64>, first_id: u64, z_at: F) -> Vec<PrePoly>
+where
+    F: Fn(i32, i32) -> f32,
+{
+    let mut poly_id = first_id;
+    let mut new_polys: Vec<PrePoly> = vec![];
+    let tris = algebra::polygon2tris(poly);
+    for tri in &tris {
+        let mut ptts: Vec<(i32, i32, f32)> = vec![];
+        ptts.push(((tri.0).0 as i32, (tri.0).1 as i32, 0.));
+        ptts.push(((tri.1).0 as i32, (tri.1).1 as i32, 0.));
+        ptts.push(((tri.2).0 as i32, (tri.2).1 as i32, 0.));
+        for i in 0..3 {
+            ptts[i].2 = z_at(ptts[i].0, ptts[i].1);
+        }
+
+        let center = (
+            (ptts[0].0 + ptts[1].0 + ptts[2].0) / 3,
+            (ptts[0].1 + ptts[1].1 + ptts[2].1) / 3,
+        );
+        let n_p = PrePoly {
+            vert_count: 3,
+            verts: ptts,
+            center: center,
+            id: poly_id,
+        };
+        new_polys.push(n_p);
+        poly_id = poly_id + 1;
+    }
+    return new_polys;
+}
+
+// local graph link between two triangles, narrow gaps cost more
+pub fn local_link(p1: &PrePoly, p2: &PrePoly) -> Option<(u64, Line)> {
+    match area_intersect(p1, p2, false) {
+        Some(edge) => {
+            let dx = p1.center.0 - p2.center.0;
+            let dy = p1.center.1 - p2.center.1;
+            let d = ((dx * dx + dy * dy) as f64).sqrt().round() as u64;
+            let edge_len = line_len(edge);
+            let weight_mul = match edge_len {
+                0..=60 => 1000.,
+                61..=100 => 100.,
+                101..=200 => 10.1,
+                _ => 1.0,
+            };
+            let weight = ((d as f64) * weight_mul).trunc() as u64;
+            return Some((weight, edge));
+        }
+        None => return None,
+    }
+}
+
 pub fn create_heatmap_graph(
     map: &Map,
     from: (i64, i64),
-    players: &Vec<((i32, i32, f32), i32)>, 
-    foes: &Vec<((i32, i32, f32), i32)>,
+    sources: &Vec<HeatSource>,
+    cost: &HeatCost,
 ) -> Option<(Graph<usize, (f64, Line)>, Vec<PrePolyWithHeat>, Vec<NodeIndex>)> {
-    let mut pos_to_z = HashMap::<(i32, i32), f32>::new();
-    let mut tiles = get_around_tiles(&map, from.0, from.1, 2520);
+    let tiles = get_around_tiles(&map, from.0, from.1, 2520);
     let mut polys = vec![];
     if tiles.len() == 0 {
         return None;
     }
 
-    // take tile's poly and z poses
-    for i_t in tiles {
-        for p in &map.polygons[i_t] {
+    // take tile's poly
+    for i_t in &tiles {
+        for p in &map.polygons[*i_t] {
             polys.push(p.clone());
         }
+    }
 
-        for area in &map.tiles[i_t].areas {
+    let heated_polys = heat_map::generate_heat_map_layout(&polys, sources);
+    return Some(heat_layout_graph(map, from, &tiles, &heated_polys, sources, cost));
+}
+
+// graph over an already heated layout of the tiles, sources give the z of their circles
+pub fn heat_layout_graph(
+    map: &Map,
+    from: (i64, i64),
+    tiles: &Vec<usize>,
+    heated_polys: &Vec<(Polygon<f64>, f64)>,
+    sources: &Vec<HeatSource>,
+    cost: &HeatCost,
+) -> (Graph<usize, (f64, Line)>, Vec<PrePolyWithHeat>, Vec<NodeIndex>) {
+    let mut pos_to_z = HashMap::<(i32, i32), f32>::new();
+
+    // take tile's z poses
+    for i_t in tiles {
+        for area in &map.tiles[*i_t].areas {
             for v in &area.verts {
                 pos_to_z.insert((v.0, v.1), v.2);
             }
         }
     }
 
-    for f in players{
-        pos_to_z.insert(((f.0).0, (f.0).1), (f.0).2);
-        pos_to_z.insert(((f.0).0 - f.1, (f.0).1 - f.1), (f.0).2);
-        pos_to_z.insert(((f.0).0 - f.1, (f.0).1 + f.1), (f.0).2);
-        pos_to_z.insert(((f.0).0 + f.1, (f.0).1 + f.1), (f.0).2);
-        pos_to_z.insert(((f.0).0 + f.1, (f.0).1 - f.1), (f.0).2);
-    }
-    for f in foes{
-        pos_to_z.insert(((f.0).0 as i32, (f.0).1 as i32), (f.0).2);
-        pos_to_z.insert(((f.0).0 - f.1, (f.0).1 - f.1), (f.0).2);
-        pos_to_z.insert(((f.0).0 - f.1, (f.0).1 + f.1), (f.0).2);
-        pos_to_z.insert(((f.0).0 + f.1, (f.0).1 + f.1), (f.0).2);
-        pos_to_z.insert(((f.0).0 + f.1, (f.0).1 - f.1), (f.0).2);
+    for source in sources{
+        pos_to_z.insert((source.pos.0, source.pos.1), source.pos.2);
+        for (disc, _) in heat_map::influence_polygons(source){
+            for c in disc.exterior().0.iter(){
+                pos_to_z.insert((c.x as i32, c.y as i32), source.pos.2);
+            }
+        }
     }
 
-    let heated_polys = heat_map::generate_heat_map_layout(&polys, players, foes);
-
     // split into triangles
     let mut poly_id: u64 = 1;
     let mut new_polys: Vec<PrePolyWithHeat> = vec![];
-    for new_p in &heated_polys {
+    for new_p in heated_polys {
         let tris = algebra::polygon2tris(&new_p.0);
         for tri in &tris{
             let mut ptts: Vec<(i32, i32, f32)> = vec![];
@@ -1301,42 +2016,354 @@ pub fn create_heatmap_graph(
         nodes.push(graph.add_node(i_poly));
     }
 
-    //make link
+    let mut portals = vec![];
     for i_poly in 0..new_polys.len() {
         for j_poly in 0..new_polys.len() {
             if i_poly == j_poly {
                 continue;
             }
             match area_intersect(&new_polys[i_poly].0, &new_polys[j_poly].0, false) {
-                Some(edge) => {
-                    let dx = (new_polys[i_poly].0).center.0 - (new_polys[j_poly].0).center.0;
-                    let dy = (new_polys[i_poly].0).center.1 - (new_polys[j_poly].0).center.1;
-                    let d = ((dx * dx + dy * dy) as f64).sqrt().round() as u64;
-
-                    let danger = new_polys[i_poly].1 + new_polys[j_poly].1;
-                    let mut weight_mul: f64 = 1.;
-                    let mut multiple: f64 = 100.0;
-                    if danger > 0 {multiple = 0.01;}
-                    for i in 0..i32::abs(danger){
-                        weight_mul = weight_mul * multiple;
+                Some(edge) => portals.push((i_poly, j_poly, edge)),
+                None => (),
+            }
+        }
+    }
+
+    // a unit standing in an impassable area may always walk out of it
+    let mut escape = HashSet::new();
+    if new_polys.len() > 0 {
+        let start = find_closest_idx_0(&new_polys, from.0, from.1);
+        let mut stack = vec![start];
+        while let Some(i_poly) = stack.pop() {
+            if heat_cost_mul(cost, new_polys[i_poly].1).is_some() || !escape.insert(i_poly) {
+                continue;
+            }
+            for (i, j, _) in &portals {
+                if *i == i_poly {
+                    stack.push(*j);
+                }
+            }
+        }
+    }
+
+    //make link
+    for (i_poly, j_poly, edge) in portals {
+        let dx = (new_polys[i_poly].0).center.0 - (new_polys[j_poly].0).center.0;
+        let dy = (new_polys[i_poly].0).center.1 - (new_polys[j_poly].0).center.1;
+        let d = ((dx * dx + dy * dy) as f64).sqrt().round() as u64;
+
+        // the edge is as dangerous as its more dangerous side
+        let heat = new_polys[i_poly].1.min(new_polys[j_poly].1);
+        let weight_mul = match heat_cost_mul(cost, heat) {
+            Some(weight_mul) => weight_mul,
+            None if escape.contains(&i_poly) => heat_map::heat_escape_mul(cost, heat),
+            None => continue,
+        };
+        let weight = (d as f64) * weight_mul;
+        links.push((nodes[i_poly], nodes[j_poly], (weight, edge)));
+    }
+    graph.extend_with_edges(links);
+    return (graph, new_polys, nodes);
+}
+
+// carved pieces have ids outside the area id range, made of their area id and piece index
+const CARVED_ID_BIT: u64 = 1 << 63;
+
+fn piece_id(area_id: u64, index: usize) -> u64 {
+    return CARVED_ID_BIT | (area_id << 16) | index as u64;
+}
+
+// the navmesh area a graph polygon was made from
+fn area_of(id: u64) -> u64 {
+    if id & CARVED_ID_BIT == 0 {
+        return id;
+    }
+    return (id & !CARVED_ID_BIT) >> 16;
+}
+
+// cut the area by every obstacle overlapping it, None if the area is untouched
+fn carve_area(area: &PrePoly, obstacles: &Vec<&Polygon<f64>>) -> Option<Vec<PrePoly>> {
+    let area_poly = prepoly_to_polygon(area);
+    let area_bounds = polygon_bounds(&area_poly);
+    let mut pieces = vec![area_poly];
+    let mut carved = false;
+
+    for obstacle in obstacles {
+        if !bounds_overlap(area_bounds, polygon_bounds(obstacle)) {
+            continue;
+        }
+        let mut rest = vec![];
+        for p in &pieces {
+            if p.intersection(*obstacle, 1.).0.len() == 0 {
+                rest.push(p.clone());
+                continue;
+            }
+            carved = true;
+            for r in p.difference(*obstacle, 1.) {
+                rest.push(r);
+            }
+        }
+        pieces = rest;
+    }
+
+    if !carved {
+        return None;
+    }
+
+    // split into triangles, new vertices take z from the area plane
+    let mut pos_to_z = HashMap::<(i32, i32), f32>::new();
+    for v in &area.verts {
+        pos_to_z.insert((v.0, v.1), v.2);
+    }
+    let mut new_polys: Vec<PrePoly> = vec![];
+    for p in &pieces {
+        for tri in algebra::polygon2tris(p) {
+            let mut ptts: Vec<(i32, i32, f32)> = vec![];
+            ptts.push(((tri.0).0 as i32, (tri.0).1 as i32, 0.));
+            ptts.push(((tri.1).0 as i32, (tri.1).1 as i32, 0.));
+            ptts.push(((tri.2).0 as i32, (tri.2).1 as i32, 0.));
+            for i in 0..3 {
+                ptts[i].2 = match pos_to_z.get(&(ptts[i].0, ptts[i].1)) {
+                    Some(z) => *z,
+                    None => get_z_from_poly(ptts[i].0, ptts[i].1, area),
+                };
+            }
+            let center = (
+                (ptts[0].0 + ptts[1].0 + ptts[2].0) / 3,
+                (ptts[0].1 + ptts[1].1 + ptts[2].1) / 3,
+            );
+            let id = piece_id(area.id, new_polys.len());
+            new_polys.push(PrePoly {
+                vert_count: 3,
+                verts: ptts,
+                center: center,
+                id: id,
+            });
+        }
+    }
+    return Some(new_polys);
+}
+
+// re-clip the areas and tile polygons inside dirty bounds and relink only their graph nodes
+fn rebuild_graph(map: &mut Map, dirty: Bounds) {
+    let obstacles: Vec<&Polygon<f64>> = map.obstacles.values().collect();
+
+    let mut areas: Vec<PrePoly> = vec![];
+    let mut area_bounds = dirty;
+    for tile in map.grid.values() {
+        for area in &tile.areas {
+            let bounds = polygon_bounds(&prepoly_to_polygon(area));
+            if !bounds_overlap(bounds, dirty) {
+                continue;
+            }
+            area_bounds = (
+                ((area_bounds.0).0.min((bounds.0).0), (area_bounds.0).1.min((bounds.0).1)),
+                ((area_bounds.1).0.max((bounds.1).0), (area_bounds.1).1.max((bounds.1).1)),
+            );
+            match carve_area(area, &obstacles) {
+                Some(pieces) => {
+                    map.carved.insert(area.id, pieces);
+                }
+                None => {
+                    map.carved.remove(&area.id);
+                }
+            }
+            areas.push(area.clone());
+        }
+    }
+
+    for i_t in 0..map.tiles.len() {
+        let bounds = map.tiles[i_t].bounds;
+        let tile_bounds = (
+            ((bounds.1).0 as f64, (bounds.1).2 as f64),
+            ((bounds.0).0 as f64, (bounds.0).2 as f64),
+        );
+        if !bounds_overlap(tile_bounds, dirty) {
+            continue;
+        }
+        let mut polys = map.base_polygons[i_t].clone();
+        for obstacle in &obstacles {
+            let mut rest = vec![];
+            for p in &polys {
+                for r in p.difference(*obstacle, 1.) {
+                    rest.push(r);
+                }
+            }
+            polys = rest;
+        }
+        map.polygons[i_t] = polys;
+    }
+
+    let dirty_ids: HashSet<u64> = areas.iter().map(|a| a.id).collect();
+    let cells = grid_cells(area_bounds);
+
+    // every polygon of a dirty area lies inside the area, so it is in the cells of area_bounds
+    let mut old: Vec<NodeIndex<u32>> = vec![];
+    for cell in &cells {
+        match map.node_grid.get(cell) {
+            Some(nodes) => {
+                for node in nodes {
+                    if dirty_ids.contains(&area_of(map.graph[*node].0)) {
+                        old.push(*node);
+                    }
+                }
+            }
+            None => (),
+        }
+    }
+    old.sort();
+    old.dedup();
+
+    for node in &old {
+        remove_walls(&mut map.walls, &mut map.wall_grid, map.graph[*node].0);
+        for cell in grid_cells(polygon_bounds(&prepoly_to_polygon(&map.graph[*node].1))) {
+            match map.node_grid.get_mut(&cell) {
+                Some(nodes) => nodes.retain(|n| n != node),
+                None => (),
+            }
+        }
+    }
+    // highest first, the last node moves into each freed index
+    for node in old.iter().rev() {
+        let last = NodeIndex::new(map.graph.node_count() - 1);
+        map.graph.remove_node(*node);
+        if *node == last {
+            continue;
+        }
+        for cell in grid_cells(polygon_bounds(&prepoly_to_polygon(&map.graph[*node].1))) {
+            match map.node_grid.get_mut(&cell) {
+                Some(nodes) => {
+                    for n in nodes.iter_mut() {
+                        if *n == last {
+                            *n = *node;
+                        }
                     }
-                    // let edge_len = line_len(edge);
-                    // let weight_mul = match edge_len {
-                    //     0..=60 => 1000.,
-                    //     61..=100 => 100.,
-                    //     101..=200 => 10.1,
-                    //     0..=100 => 3.2,
-                    //     101..=200 => 2.1,
-                    //     _ => 1.0,
-                    // };
-                    let weight = (d as f64) * weight_mul;
-                    links.push((nodes[i_poly], nodes[j_poly], (weight, edge)));
                 }
                 None => (),
             }
         }
     }
-    graph.extend_with_edges(links);
-    return Some((graph, new_polys, nodes));
+
+    // carved pieces replace their source area in the graph
+    let mut nodes_of = HashMap::<u64, Vec<NodeIndex<u32>>>::new();
+    for area in &areas {
+        let polys = match map.carved.get(&area.id) {
+            Some(pieces) => pieces.clone(),
+            None => vec![area.clone()],
+        };
+        let mut nodes = vec![];
+        for p in polys {
+            let bounds = polygon_bounds(&prepoly_to_polygon(&p));
+            let node = map.graph.add_node((p.id, p));
+            for cell in grid_cells(bounds) {
+                map.node_grid.entry(cell).or_insert(vec![]).push(node);
+            }
+            nodes.push(node);
+        }
+        nodes_of.insert(area.id, nodes);
+    }
+    // neighbours of the dirty areas touch them, so they are in the same cells
+    for cell in &cells {
+        match map.node_grid.get(cell) {
+            Some(nodes) => {
+                for node in nodes {
+                    let area_id = area_of(map.graph[*node].0);
+                    if !dirty_ids.contains(&area_id) {
+                        nodes_of.entry(area_id).or_insert(vec![]).push(*node);
+                    }
+                }
+            }
+            None => (),
+        }
+    }
+    for nodes in nodes_of.values_mut() {
+        nodes.sort();
+        nodes.dedup();
+    }
+
+    let mut edges = vec![];
+    for (a, b, line) in &map.links {
+        if !dirty_ids.contains(a) && !dirty_ids.contains(b) {
+            continue;
+        }
+        let (an, bn) = match (nodes_of.get(a), nodes_of.get(b)) {
+            (Some(an), Some(bn)) => (an, bn),
+            _ => continue,
+        };
+        if !map.carved.contains_key(a) && !map.carved.contains_key(b) {
+            let weight = link_weight(&map.graph[an[0]].1, &map.graph[bn[0]].1, *line);
+            edges.push((an[0], bn[0], (weight, line.clone())));
+            continue;
+        }
+        // portals of carved pieces come from their geometry, links are stored both ways
+        for x in an {
+            for y in bn {
+                match area_intersect(&map.graph[*x].1, &map.graph[*y].1, false) {
+                    Some(edge) => {
+                        let weight = link_weight(&map.graph[*x].1, &map.graph[*y].1, edge);
+                        edges.push((*x, *y, (weight, edge)));
+                    }
+                    None => (),
+                }
+            }
+        }
+    }
+    // pieces of the same area with each other
+    for area in &areas {
+        if !map.carved.contains_key(&area.id) {
+            continue;
+        }
+        let nodes = &nodes_of[&area.id];
+        for x in nodes {
+            for y in nodes {
+                if x == y {
+                    continue;
+                }
+                match area_intersect(&map.graph[*x].1, &map.graph[*y].1, false) {
+                    Some(edge) => {
+                        let weight = link_weight(&map.graph[*x].1, &map.graph[*y].1, edge);
+                        edges.push((*x, *y, (weight, edge)));
+                    }
+                    None => (),
+                }
+            }
+        }
+    }
+    map.graph.extend_with_edges(edges);
+
+    // portals changed for the new polygons and their neighbours
+    for nodes in nodes_of.values() {
+        for node in nodes {
+            let id = map.graph[*node].0;
+            remove_walls(&mut map.walls, &mut map.wall_grid, id);
+            let poly_walls = node_walls(&map.graph, *node);
+            insert_walls(&mut map.walls, &mut map.wall_grid, id, poly_walls);
+        }
+    }
+    map.revision = map.revision + 1;
 }
 
+pub fn add_obstacle(map: &mut Map, id: u64, shape: Polygon<f64>) {
+    let mut dirty = polygon_bounds(&shape);
+    match map.obstacles.insert(id, shape) {
+        Some(prev) => {
+            let prev_bounds = polygon_bounds(&prev);
+            dirty = (
+                ((dirty.0).0.min((prev_bounds.0).0), (dirty.0).1.min((prev_bounds.0).1)),
+                ((dirty.1).0.max((prev_bounds.1).0), (dirty.1).1.max((prev_bounds.1).1)),
+            );
+        }
+        None => (),
+    }
+    rebuild_graph(map, dirty);
+}
+
+pub fn remove_obstacle(map: &mut Map, id: u64) -> bool {
+    match map.obstacles.remove(&id) {
+        Some(shape) => {
+            rebuild_graph(map, polygon_bounds(&shape));
+            return true;
+        }
+        None => return false,
+    }
+}
@@ -0,0 +1,107 @@
+use crate::mesh_geo;
+
+// how far a reported height may be from the mesh surface
+const HEIGHT_TOLERANCE: f32 = 100.0;
+// largest height step allowed between two connected polygons
+const MAX_CLIMB: f32 = 150.0;
+
+pub enum Violation {
+    OffMesh,
+    TooFast,
+    Wall,
+    Height,
+}
+
+fn dist(a: (f64, f64), b: (f64, f64)) -> f64 {
+    return (b.0 - a.0).hypot(b.1 - a.1);
+}
+
+// height jump between consecutive polygons, measured at the middle of their portal
+fn max_climb(map: &mesh_geo::Map, nodes: &Vec<petgraph::graph::NodeIndex<u32>>) -> f32 {
+    let mut climb: f32 = 0.0;
+    for i in 1..nodes.len() {
+        match map.graph.find_edge(nodes[i - 1], nodes[i]) {
+            Some(edge) => {
+                let (_, (a, b)) = map.graph[edge];
+                let (mx, my) = ((a.0 + b.0) / 2, (a.1 + b.1) / 2);
+                let z1 = mesh_geo::get_z_from_poly(mx, my, &map.graph[nodes[i - 1]].1);
+                let z2 = mesh_geo::get_z_from_poly(mx, my, &map.graph[nodes[i]].1);
+                climb = climb.max((z1 - z2).abs());
+            }
+            None => (),
+        }
+    }
+    return climb;
+}
+
+// the closest valid position to the reported one, reachable from the last good position
+fn correct(
+    map: &mesh_geo::Map,
+    last: Option<(f64, f64)>,
+    to: (f64, f64),
+    max_step: f64,
+) -> (f64, f64, f32) {
+    match last {
+        Some(from) => {
+            let d = dist(from, to);
+            let mut target = to;
+            if d > max_step {
+                let t = max_step / d;
+                target = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+            }
+            match mesh_geo::move_along_surface(map, from, target) {
+                Some((point, _)) => return point,
+                None => (),
+            }
+        }
+        None => (),
+    }
+    let node = mesh_geo::find_closest_node(&map.graph, to.0 as i64, to.1 as i64);
+    let poly = &map.graph[node].1;
+    let z = mesh_geo::get_z_from_poly(poly.center.0, poly.center.1, poly);
+    return (poly.center.0 as f64, poly.center.1 as f64, z);
+}
+
+// first step that leaves the mesh, goes through a wall, climbs too high or moves too fast
+pub fn validate(
+    map: &mesh_geo::Map,
+    points: &Vec<(i64, i64, f32)>,
+    max_speed: f64,
+    dt: f64,
+) -> Option<(usize, Violation, (f64, f64, f32))> {
+    // a little slack for rounding on the client
+    let max_step = max_speed * dt * 1.01 + 1.0;
+    let mut last: Option<(f64, f64)> = None;
+    for (i, p) in points.iter().enumerate() {
+        let pos = (p.0 as f64, p.1 as f64);
+        let node = match mesh_geo::find_node_at(map, pos.0, pos.1) {
+            Some(node) => node,
+            None => return Some((i, Violation::OffMesh, correct(map, last, pos, max_step))),
+        };
+        let z = mesh_geo::get_z_from_poly(p.0 as i32, p.1 as i32, &map.graph[node].1);
+        if (z - p.2).abs() > HEIGHT_TOLERANCE {
+            return Some((i, Violation::Height, correct(map, last, pos, max_step)));
+        }
+        match last {
+            Some(from) => {
+                if dist(from, pos) > max_step {
+                    return Some((i, Violation::TooFast, correct(map, last, pos, max_step)));
+                }
+                match mesh_geo::raycast(map, from, pos) {
+                    Some(hit) => {
+                        if hit.hit {
+                            return Some((i, Violation::Wall, correct(map, last, pos, max_step)));
+                        }
+                        if max_climb(map, &hit.visited) > MAX_CLIMB {
+                            return Some((i, Violation::Height, correct(map, last, pos, max_step)));
+                        }
+                    }
+                    None => return Some((i, Violation::OffMesh, correct(map, last, pos, max_step))),
+                }
+            }
+            None => (),
+        }
+        last = Some(pos);
+    }
+    return None;
+}
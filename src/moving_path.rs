@@ -0,0 +1,129 @@
+use std::sync::Mutex;
+
+// a walked path, times are in whatever unit the speeds are given per
+pub struct MovingPath {
+    state: Mutex<PathState>,
+}
+
+struct PathState {
+    points: Vec<(f64, f64, f32)>,
+    // distance from the first point to each point
+    cumulative: Vec<f64>,
+    // (start time, distance at start time, speed), one entry per speed change
+    speeds: Vec<(f64, f64, f64)>,
+}
+
+fn dist3(a: (f64, f64, f32), b: (f64, f64, f32)) -> f64 {
+    let dz = (b.2 - a.2) as f64;
+    return ((b.0 - a.0) * (b.0 - a.0) + (b.1 - a.1) * (b.1 - a.1) + dz * dz).sqrt();
+}
+
+impl PathState {
+    fn total(&self) -> f64 {
+        return self.cumulative[self.cumulative.len() - 1];
+    }
+
+    fn distance_at(&self, t: f64) -> f64 {
+        let mut current = self.speeds[0];
+        for s in &self.speeds {
+            if s.0 > t {
+                break;
+            }
+            current = *s;
+        }
+        let (t0, d0, speed) = current;
+        return (d0 + speed * (t - t0)).max(0.0).min(self.total());
+    }
+
+    fn point_at_distance(&self, d: f64) -> (f64, f64, f32) {
+        // first point at or beyond d
+        let i = match self
+            .cumulative
+            .binary_search_by(|c| c.partial_cmp(&d).unwrap())
+        {
+            Ok(i) => return self.points[i],
+            Err(i) => i,
+        };
+        if i == 0 {
+            return self.points[0];
+        }
+        if i >= self.points.len() {
+            return self.points[self.points.len() - 1];
+        }
+        let (a, b) = (self.points[i - 1], self.points[i]);
+        let t = (d - self.cumulative[i - 1]) / (self.cumulative[i] - self.cumulative[i - 1]);
+        return (
+            a.0 + (b.0 - a.0) * t,
+            a.1 + (b.1 - a.1) * t,
+            a.2 + (b.2 - a.2) * t as f32,
+        );
+    }
+}
+
+impl MovingPath {
+    pub fn new(points: Vec<(f64, f64, f32)>, speed: f64, start_time: f64) -> Option<MovingPath> {
+        if points.len() == 0 {
+            return None;
+        }
+        let mut cumulative = vec![0.0];
+        for i in 1..points.len() {
+            let d = cumulative[i - 1] + dist3(points[i - 1], points[i]);
+            cumulative.push(d);
+        }
+        return Some(MovingPath {
+            state: Mutex::new(PathState {
+                points: points,
+                cumulative: cumulative,
+                speeds: vec![(start_time, 0.0, speed)],
+            }),
+        });
+    }
+
+    pub fn position_at(&self, t: f64) -> (f64, f64, f32) {
+        let state = self.state.lock().unwrap();
+        return state.point_at_distance(state.distance_at(t));
+    }
+
+    pub fn remaining_distance(&self, t: f64) -> f64 {
+        let state = self.state.lock().unwrap();
+        return state.total() - state.distance_at(t);
+    }
+
+    // distance along the path of the closest path point, and the fraction of the path it is at
+    pub fn progress_to(&self, x: f64, y: f64) -> (f64, f64) {
+        let state = self.state.lock().unwrap();
+        let mut best = (std::f64::MAX, 0.0);
+        for i in 0..state.points.len() {
+            let a = state.points[i];
+            let (mut cx, mut cy, mut along) = (a.0, a.1, state.cumulative[i]);
+            if i + 1 < state.points.len() {
+                let b = state.points[i + 1];
+                let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+                let len2 = dx * dx + dy * dy;
+                if len2 > 0.0 {
+                    let t = (((x - a.0) * dx + (y - a.1) * dy) / len2).max(0.0).min(1.0);
+                    cx = a.0 + dx * t;
+                    cy = a.1 + dy * t;
+                    along = state.cumulative[i] + (state.cumulative[i + 1] - state.cumulative[i]) * t;
+                }
+            }
+            let d2 = (cx - x) * (cx - x) + (cy - y) * (cy - y);
+            if d2 < best.0 {
+                best = (d2, along);
+            }
+        }
+        let total = state.total();
+        if total == 0.0 {
+            return (0.0, 1.0);
+        }
+        return (best.1, best.1 / total);
+    }
+
+    // speed from time t on, what was walked before t is kept
+    pub fn set_speed(&self, t: f64, speed: f64) {
+        let mut state = self.state.lock().unwrap();
+        let d = state.distance_at(t);
+        state.speeds.retain(|s| s.0 < t);
+        state.speeds.push((t, d, speed));
+    }
+}
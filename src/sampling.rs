@@ -0,0 +1,175 @@
+use crate::mesh_geo;
+use crate::mesh_geo::PrePoly;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// xorshift64*, enough for picking wander spots and reproducible with a seed
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // a zero state would stay zero forever
+        let mut state = seed ^ 0x9E37_79B9_7F4A_7C15;
+        if state == 0 {
+            state = 0x9E37_79B9_7F4A_7C15;
+        }
+        return Rng { state: state };
+    }
+
+    pub fn from_time() -> Rng {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        return Rng::new(nanos);
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        return x.wrapping_mul(0x2545_F491_4F6C_DD1D);
+    }
+
+    // uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        return (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+    }
+}
+
+// sides of the polygon the sampling circle is approximated with, inscribed so points stay inside
+const CIRCLE_SIDES: usize = 32;
+
+fn tri_area(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    return ((b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1)).abs() / 2.0;
+}
+
+fn convex_area(v: &Vec<(f64, f64)>) -> f64 {
+    let mut total = 0.0;
+    for i in 1..(v.len().max(2) - 1) {
+        total += tri_area(v[0], v[i], v[i + 1]);
+    }
+    return total;
+}
+
+// uniform point inside a convex polygon: pick a fan triangle by area, then a point in it
+fn point_in_convex(v: &Vec<(f64, f64)>, rng: &mut Rng) -> (f64, f64) {
+    let mut areas = vec![];
+    let mut total = 0.0;
+    for i in 1..(v.len() - 1) {
+        total += tri_area(v[0], v[i], v[i + 1]);
+        areas.push(total);
+    }
+
+    let pick = rng.next_f64() * total;
+    let mut i_tri = areas.len() - 1;
+    for i in 0..areas.len() {
+        if pick < areas[i] {
+            i_tri = i;
+            break;
+        }
+    }
+
+    let (a, b, c) = (v[0], v[i_tri + 1], v[i_tri + 2]);
+    let r1 = rng.next_f64().sqrt();
+    let r2 = rng.next_f64();
+    let x = (1.0 - r1) * a.0 + r1 * (1.0 - r2) * b.0 + r1 * r2 * c.0;
+    let y = (1.0 - r1) * a.1 + r1 * (1.0 - r2) * b.1 + r1 * r2 * c.1;
+    return (x, y);
+}
+
+// the convex polygon cut to the circle, edge by edge (Sutherland-Hodgman)
+fn clip_to_circle(poly: &PrePoly, center: (f64, f64), radius: f64) -> Vec<(f64, f64)> {
+    let mut v: Vec<(f64, f64)> = poly.verts.iter().map(|p| (p.0 as f64, p.1 as f64)).collect();
+    if v.iter().all(|p| (p.0 - center.0).hypot(p.1 - center.1) <= radius * (std::f64::consts::PI / CIRCLE_SIDES as f64).cos()) {
+        return v;
+    }
+    for i in 0..CIRCLE_SIDES {
+        let a0 = 2.0 * std::f64::consts::PI * i as f64 / CIRCLE_SIDES as f64;
+        let a1 = 2.0 * std::f64::consts::PI * (i + 1) as f64 / CIRCLE_SIDES as f64;
+        let e0 = (center.0 + radius * a0.cos(), center.1 + radius * a0.sin());
+        let e1 = (center.0 + radius * a1.cos(), center.1 + radius * a1.sin());
+        // counter-clockwise edges, inside is on the left
+        let side = |p: (f64, f64)| (e1.0 - e0.0) * (p.1 - e0.1) - (e1.1 - e0.1) * (p.0 - e0.0);
+
+        let mut out = vec![];
+        for j in 0..v.len() {
+            let (p, q) = (v[j], v[(j + 1) % v.len()]);
+            let (sp, sq) = (side(p), side(q));
+            if sp >= 0.0 {
+                out.push(p);
+            }
+            if (sp >= 0.0) != (sq >= 0.0) {
+                let t = sp / (sp - sq);
+                out.push((p.0 + (q.0 - p.0) * t, p.1 + (q.1 - p.1) * t));
+            }
+        }
+        v = out;
+        if v.len() < 3 {
+            return vec![];
+        }
+    }
+    return v;
+}
+
+// count uniform points within radius of center, on the polygons within radius of walking between
+// polygon centers, with the walking distance to each
+pub fn random_points(
+    map: &mesh_geo::Map,
+    center: (i64, i64),
+    radius: u64,
+    count: usize,
+    rng: &mut Rng,
+) -> Vec<((i64, i64, f32), u64)> {
+    let reached = mesh_geo::reachable_polys(map, center, radius);
+    let mut pieces = vec![];
+    let mut areas = vec![];
+    let mut total = 0.0;
+    for (node, cost) in &reached {
+        // a unit smaller, so rounding to integer positions stays in radius
+        let piece = clip_to_circle(&(map.graph[*node]).1, (center.0 as f64, center.1 as f64), radius as f64 - 1.0);
+        let area = convex_area(&piece);
+        if area <= 0.0 {
+            continue;
+        }
+        total += area;
+        areas.push(total);
+        pieces.push((*node, *cost, piece));
+    }
+    if total <= 0.0 {
+        return vec![];
+    }
+
+    let mut points = vec![];
+    for _ in 0..count {
+        let pick = rng.next_f64() * total;
+        let mut i_poly = areas.len() - 1;
+        for i in 0..areas.len() {
+            if pick < areas[i] {
+                i_poly = i;
+                break;
+            }
+        }
+        let (node, cost, piece) = &pieces[i_poly];
+        let (x, y) = point_in_convex(piece, rng);
+        let z = mesh_geo::get_z_from_poly(x as i32, y as i32, &(map.graph[*node]).1);
+        points.push(((x.round() as i64, y.round() as i64, z), *cost));
+    }
+    return points;
+}
+
+pub fn random_point(
+    map: &mesh_geo::Map,
+    center: (i64, i64),
+    radius: u64,
+    rng: &mut Rng,
+) -> Option<(i64, i64, f32)> {
+    match random_points(map, center, radius, 1, rng).pop() {
+        Some((point, _)) => return Some(point),
+        None => return None,
+    }
+}
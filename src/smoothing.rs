@@ -0,0 +1,172 @@
+use crate::mesh_geo;
+
+fn clear_line(map: &mesh_geo::Map, a: (f64, f64, f32), b: (f64, f64, f32)) -> bool {
+    return match mesh_geo::raycast(map, (a.0, a.1), (b.0, b.1)) {
+        Some(hit) => !hit.hit,
+        None => false,
+    };
+}
+
+// every step from start through the curve to end has a clear line on the navmesh
+fn walkable(
+    map: &mesh_geo::Map,
+    start: (f64, f64, f32),
+    curve: &Vec<(f64, f64, f32)>,
+    end: (f64, f64, f32),
+) -> bool {
+    let mut prev = start;
+    for p in curve.iter() {
+        if !clear_line(map, prev, *p) {
+            return false;
+        }
+        prev = *p;
+    }
+    return clear_line(map, prev, end);
+}
+
+fn seg_len(a: (f64, f64, f32), b: (f64, f64, f32)) -> f64 {
+    return ((b.0 - a.0) * (b.0 - a.0) + (b.1 - a.1) * (b.1 - a.1)).sqrt();
+}
+
+fn lerp(a: (f64, f64, f32), b: (f64, f64, f32), t: f64) -> (f64, f64, f32) {
+    return (
+        a.0 + (b.0 - a.0) * t,
+        a.1 + (b.1 - a.1) * t,
+        a.2 + (b.2 - a.2) * t as f32,
+    );
+}
+
+// keep the curve if no step along it crosses a wall, otherwise the straight span
+fn push_span(
+    map: &mesh_geo::Map,
+    re: &mut Vec<(f64, f64, f32)>,
+    curve: Vec<(f64, f64, f32)>,
+    end: (f64, f64, f32),
+) {
+    let start = re[re.len() - 1];
+    if walkable(map, start, &curve, end) {
+        for p in curve {
+            re.push(p);
+        }
+    }
+    re.push(end);
+}
+
+pub fn catmull_rom(
+    map: &mesh_geo::Map,
+    points: &Vec<(f64, f64, f32)>,
+    step: f64,
+) -> Vec<(f64, f64, f32)> {
+    if points.len() < 3 {
+        return points.clone();
+    }
+    let mut re = vec![points[0]];
+    for i in 0..(points.len() - 1) {
+        let p0 = points[if i == 0 { 0 } else { i - 1 }];
+        let p1 = points[i];
+        let p2 = points[i + 1];
+        let p3 = points[if i + 2 < points.len() { i + 2 } else { i + 1 }];
+
+        let samples = (seg_len(p1, p2) / step).ceil().max(1.0) as usize;
+        let mut curve = vec![];
+        for s in 1..samples {
+            let t = s as f64 / samples as f64;
+            let t2 = t * t;
+            let t3 = t2 * t;
+            let x = 0.5
+                * (2.0 * p1.0
+                    + (p2.0 - p0.0) * t
+                    + (2.0 * p0.0 - 5.0 * p1.0 + 4.0 * p2.0 - p3.0) * t2
+                    + (3.0 * p1.0 - p0.0 - 3.0 * p2.0 + p3.0) * t3);
+            let y = 0.5
+                * (2.0 * p1.1
+                    + (p2.1 - p0.1) * t
+                    + (2.0 * p0.1 - 5.0 * p1.1 + 4.0 * p2.1 - p3.1) * t2
+                    + (3.0 * p1.1 - p0.1 - 3.0 * p2.1 + p3.1) * t3);
+            curve.push((x, y, lerp(p1, p2, t).2));
+        }
+        push_span(map, &mut re, curve, p2);
+    }
+    return re;
+}
+
+// round every corner with a quadratic bezier starting radius before it
+pub fn bezier(
+    map: &mesh_geo::Map,
+    points: &Vec<(f64, f64, f32)>,
+    radius: f64,
+    step: f64,
+) -> Vec<(f64, f64, f32)> {
+    if points.len() < 3 {
+        return points.clone();
+    }
+    let mut re = vec![points[0]];
+    for i in 1..(points.len() - 1) {
+        let (prev, corner, next) = (points[i - 1], points[i], points[i + 1]);
+        let l1 = seg_len(prev, corner);
+        let l2 = seg_len(corner, next);
+        if l1 == 0.0 || l2 == 0.0 {
+            re.push(corner);
+            continue;
+        }
+        let r = radius.min(l1 / 2.0).min(l2 / 2.0);
+        let a = lerp(corner, prev, r / l1);
+        let b = lerp(corner, next, r / l2);
+
+        re.push(a);
+        let samples = ((seg_len(a, corner) + seg_len(corner, b)) / step).ceil().max(1.0) as usize;
+        let mut curve = vec![];
+        for s in 1..samples {
+            let t = s as f64 / samples as f64;
+            let ab = lerp(lerp(a, corner, t), lerp(corner, b, t), t);
+            curve.push(ab);
+        }
+        if walkable(map, a, &curve, b) {
+            for p in curve {
+                re.push(p);
+            }
+        } else {
+            re.push(corner);
+        }
+        re.push(b);
+    }
+    re.push(points[points.len() - 1]);
+    return re;
+}
+
+// one point every step along the polyline, z taken from the polygon under it
+pub fn resample(
+    map: &mesh_geo::Map,
+    points: &Vec<(f64, f64, f32)>,
+    step: f64,
+) -> Vec<(i64, i64, f32)> {
+    let mut re = vec![];
+    if points.len() == 0 {
+        return re;
+    }
+    let mut sampled = vec![points[0]];
+    let mut carry = 0.0;
+    for i in 0..(points.len() - 1) {
+        let (a, b) = (points[i], points[i + 1]);
+        let len = seg_len(a, b);
+        let mut d = step - carry;
+        while d <= len {
+            sampled.push(lerp(a, b, d / len));
+            d += step;
+        }
+        carry = len - (d - step);
+    }
+    let last = points[points.len() - 1];
+    if carry > 0.0 {
+        sampled.push(last);
+    }
+
+    for p in sampled {
+        let z = match mesh_geo::find_node_at(map, p.0, p.1) {
+            Some(node) => mesh_geo::get_z_from_poly(p.0 as i32, p.1 as i32, &map.graph[node].1),
+            None => p.2,
+        };
+        re.push((p.0 as i64, p.1 as i64, z));
+    }
+    return re;
+}
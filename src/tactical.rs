@@ -0,0 +1,104 @@
+use crate::influence;
+use crate::mesh_geo;
+use crate::sampling;
+
+// walls further than this give no cover
+const WALL_RANGE: f64 = 300.0;
+
+// each sample costs a raycast per threat and a wall query
+pub const MAX_SAMPLES: usize = 256;
+
+// weights of each score term, zero leaves the term out
+pub struct Criteria {
+    // heat layer, friendly heat is positive
+    pub heat: Option<(String, f64)>,
+    pub threats: Vec<(f64, f64)>,
+    // per search radius of distance to the closest threat, negative to close in
+    pub threat_distance: f64,
+    // share of threats in line of sight, negative to look for cover
+    pub line_of_sight: f64,
+    // closeness to a wall
+    pub wall: f64,
+    // per search radius of walking distance, positive to prefer closer spots
+    pub travel: f64,
+    // at most MAX_SAMPLES
+    pub samples: usize,
+    pub top: usize,
+}
+
+impl Default for Criteria {
+    fn default() -> Criteria {
+        return Criteria {
+            heat: None,
+            threats: vec![],
+            threat_distance: 0.0,
+            line_of_sight: 0.0,
+            wall: 0.0,
+            travel: 0.0,
+            samples: 64,
+            top: 5,
+        };
+    }
+}
+
+fn score(map_id: i64, map: &mesh_geo::Map, point: (f64, f64), cost: u64, radius: f64, criteria: &Criteria) -> f64 {
+    let mut score = 0.0;
+    match &criteria.heat {
+        Some((layer, weight)) => match influence::heat_at(map_id, layer, point.0, point.1) {
+            Some(heat) => score += weight * heat,
+            None => (),
+        },
+        None => (),
+    }
+    if criteria.threats.len() > 0 {
+        if criteria.threat_distance != 0.0 {
+            let closest = criteria
+                .threats
+                .iter()
+                .map(|t| (t.0 - point.0).hypot(t.1 - point.1))
+                .fold(std::f64::MAX, f64::min);
+            score += criteria.threat_distance * closest / radius;
+        }
+        if criteria.line_of_sight != 0.0 {
+            let mut visible = 0;
+            for t in &criteria.threats {
+                match mesh_geo::raycast(map, point, *t) {
+                    Some(hit) if !hit.hit => visible += 1,
+                    _ => (),
+                }
+            }
+            score += criteria.line_of_sight * visible as f64 / criteria.threats.len() as f64;
+        }
+    }
+    if criteria.wall != 0.0 {
+        match mesh_geo::distance_to_wall(map, point.0, point.1, WALL_RANGE) {
+            Some((d, _, _)) => score += criteria.wall * (1.0 - d / WALL_RANGE),
+            None => (),
+        }
+    }
+    score -= criteria.travel * cost as f64 / radius;
+    return score;
+}
+
+// samples positions within radius of walking between polygon centers, as reachable_polys,
+// and returns the best scored, best first
+pub fn find_positions(
+    map_id: i64,
+    map: &mesh_geo::Map,
+    from: (i64, i64),
+    radius: u64,
+    criteria: &Criteria,
+    rng: &mut sampling::Rng,
+) -> Vec<((i64, i64, f32), f64)> {
+    let points = sampling::random_points(map, from, radius, criteria.samples.min(MAX_SAMPLES), rng);
+    let mut scored: Vec<((i64, i64, f32), f64)> = points
+        .iter()
+        .map(|(p, cost)| {
+            let s = score(map_id, map, (p.0 as f64, p.1 as f64), *cost, radius.max(1) as f64, criteria);
+            return (*p, s);
+        })
+        .collect();
+    scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    scored.truncate(criteria.top);
+    return scored;
+}